@@ -0,0 +1,200 @@
+/*!
+*  Linkwitz-Riley crossovers, for splitting a signal into phase-coherent
+*  bands, e.g. for multiband dynamics or saturation.
+*
+*  A Linkwitz-Riley filter of order `2n` is a Butterworth filter of order `n`
+*  applied twice. Its lowpass and highpass outputs are both at -6dB at the
+*  crossover frequency, and sum to an allpass: the bands can be recombined
+*  without changing the magnitude response.
+*/
+
+use rustfft::num_traits::Float;
+
+use crate::effects::filter::BiquadCoefs;
+use crate::effects::filter_design::{self, Band, Cascade, Prototype};
+
+/// Order of a Linkwitz-Riley crossover.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CrossoverOrder {
+    /// 12dB/octave. The highpass output is inverted, so that the bands still
+    /// sum to an allpass.
+    Lr2,
+    /// 24dB/octave.
+    Lr4,
+    /// 48dB/octave.
+    Lr8,
+}
+
+impl CrossoverOrder {
+    /// Order of the underlying Butterworth filters.
+    fn butterworth_order(self) -> usize {
+        match self {
+            CrossoverOrder::Lr2 => 1,
+            CrossoverOrder::Lr4 => 2,
+            CrossoverOrder::Lr8 => 4,
+        }
+    }
+}
+
+/// Two-band Linkwitz-Riley crossover.
+pub struct Crossover<T> {
+    lp: Cascade<T>,
+    hp: Cascade<T>,
+    order: CrossoverOrder,
+    sr: f64,
+}
+
+impl<T: Float> Crossover<T> {
+    /// Create a new crossover.
+    /// # Parameters
+    /// - order: order of the crossover
+    /// - freq: crossover frequency in hertz
+    /// - sr: sample rate in hertz
+    pub fn new(order: CrossoverOrder, freq: f64, sr: f64) -> Self {
+        let mut ret = Self {
+            lp: Cascade::new(&[]),
+            hp: Cascade::new(&[]),
+            order: order,
+            sr: sr,
+        };
+        ret.set_freq(freq);
+        return ret;
+    }
+
+    /// Set the crossover frequency in hertz. The state of the filters is kept.
+    pub fn set_freq(&mut self, freq: f64) {
+        let (lp, hp) = crossover_sections(self.order, freq, self.sr);
+        self.lp.set_coefs(&lp);
+        self.hp.set_coefs(&hp);
+    }
+
+    /// Split a sample into bands.
+    /// # Returns
+    /// - low and high bands
+    pub fn process(&mut self, x: T) -> (T, T) {
+        let low = self.lp.filter(x);
+        let high = self.hp.filter(x);
+        if self.order == CrossoverOrder::Lr2 {
+            return (low, -high);
+        }
+        return (low, high);
+    }
+
+    /// Reset the state of the filters.
+    pub fn reset(&mut self) {
+        self.lp.reset();
+        self.hp.reset();
+    }
+}
+
+/// Splits a signal into any number of bands with Linkwitz-Riley crossovers.
+///
+/// The crossovers are chained from the lowest frequency: the high band of
+/// each crossover is split again by the next one. The lower bands are run
+/// through allpasses matching the phase of the crossovers above them, so that
+/// all the bands sum to an allpass.
+pub struct MultibandSplitter<T> {
+    crossovers: Vec<Crossover<T>>,
+    // allpasses[band][j] compensates the crossover `band + 1 + j`
+    allpasses: Vec<Vec<Cascade<T>>>,
+    freqs: Vec<f64>,
+    order: CrossoverOrder,
+    sr: f64,
+}
+
+impl<T: Float> MultibandSplitter<T> {
+    /// Create a new splitter with `freqs.len() + 1` bands.
+    /// # Parameters
+    /// - order: order of the crossovers
+    /// - freqs: crossover frequencies in hertz, sorted in ascending order
+    /// - sr: sample rate in hertz
+    pub fn new(order: CrossoverOrder, freqs: &[f64], sr: f64) -> Self {
+        let n = freqs.len();
+        let mut ret = Self {
+            crossovers: freqs.iter().map(|f| Crossover::new(order, *f, sr)).collect(),
+            allpasses: (0..n).map(|band| {
+                (band + 1..n).map(|_| Cascade::new(&[])).collect()
+            }).collect(),
+            freqs: freqs.to_vec(),
+            order: order,
+            sr: sr,
+        };
+        for (i, f) in freqs.iter().enumerate() {
+            ret.set_freq(i, *f);
+        }
+        return ret;
+    }
+
+    /// Number of bands produced by the splitter.
+    pub fn bands(&self) -> usize {
+        self.crossovers.len() + 1
+    }
+
+    /// Set the frequency of a crossover in hertz. Frequencies should stay in
+    /// ascending order.
+    /// # Returns
+    /// - false if there is no crossover with this index
+    pub fn set_freq(&mut self, index: usize, freq: f64) -> bool {
+        if index >= self.crossovers.len() {
+            return false;
+        }
+        self.freqs[index] = freq;
+        self.crossovers[index].set_freq(freq);
+        let allpass = allpass_sections(self.order, freq, self.sr);
+        for band in 0..index {
+            self.allpasses[band][index - band - 1].set_coefs(&allpass);
+        }
+        return true;
+    }
+
+    /// Frequency of a crossover in hertz.
+    pub fn freq(&self, index: usize) -> Option<f64> {
+        self.freqs.get(index).copied()
+    }
+
+    /// Split a sample into bands.
+    /// # Parameters
+    /// - x: input sample
+    /// - bands: output, from the lowest to the highest band. Must hold at
+    ///   least `bands()` samples.
+    pub fn process(&mut self, x: T, bands: &mut [T]) {
+        let mut rest = x;
+        for (i, crossover) in self.crossovers.iter_mut().enumerate() {
+            let (low, high) = crossover.process(rest);
+            bands[i] = self.allpasses[i].iter_mut().fold(low, |acc, ap| ap.filter(acc));
+            rest = high;
+        }
+        bands[self.crossovers.len()] = rest;
+    }
+
+    /// Reset the state of the filters.
+    pub fn reset(&mut self) {
+        self.crossovers.iter_mut().for_each(|c| c.reset());
+        self.allpasses.iter_mut().flatten().for_each(|ap| ap.reset());
+    }
+}
+
+/// Sections of the lowpass and highpass of a crossover.
+fn crossover_sections(order: CrossoverOrder, freq: f64, sr: f64) -> (Vec<BiquadCoefs<f64>>, Vec<BiquadCoefs<f64>>) {
+    let n = order.butterworth_order();
+    let lp = filter_design::design(Prototype::Butterworth, Band::Lowpass, n, freq, sr);
+    let hp = filter_design::design(Prototype::Butterworth, Band::Highpass, n, freq, sr);
+    return (lp.repeat(2), hp.repeat(2));
+}
+
+/// Sections of the allpass equal to the sum of the bands of a crossover.
+/// Each section has the poles of the Butterworth filter, and its numerator is
+/// the mirrored denominator.
+fn allpass_sections(order: CrossoverOrder, freq: f64, sr: f64) -> Vec<BiquadCoefs<f64>> {
+    let n = order.butterworth_order();
+    filter_design::design(Prototype::Butterworth, Band::Lowpass, n, freq, sr)
+        .iter()
+        .map(|s| {
+            if s.a2 == 0.0 {
+                BiquadCoefs { b0: s.a1, b1: 1.0, b2: 0.0, a1: s.a1, a2: 0.0 }
+            } else {
+                BiquadCoefs { b0: s.a2, b1: s.a1, b2: 1.0, a1: s.a1, a2: s.a2 }
+            }
+        })
+        .collect()
+}
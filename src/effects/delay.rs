@@ -1,23 +1,227 @@
-use std::collections::VecDeque;
+use std::f32::consts;
 
 use crate::utils::math;
 
+/// Half the number of taps of the windowed-sinc interpolator.
+const SINC_HALF_TAPS: usize = 8;
+
+/// Number of fractional positions tabulated for the windowed-sinc
+/// interpolator. Positions in between are linearly interpolated.
+const SINC_PHASES: usize = 256;
+
+/// Extra samples allocated past the end of the delay line, so that
+/// interpolators can read the neighbours of the oldest sample without
+/// wrapping into the newest ones.
+const INTERP_MARGIN: usize = SINC_HALF_TAPS;
+
+/// Fixed-capacity circular buffer with a power-of-two capacity, so that
+/// wrapping the indexes is a single bitwise and.
+pub(crate) struct RingBuffer {
+    data: Vec<f32>,
+    mask: usize,
+    write_idx: usize,
+}
+
+impl RingBuffer {
+    /// Allocate a buffer that can hold at least `len` samples.
+    pub(crate) fn new(len: usize) -> Self {
+        let capacity = len.max(1).next_power_of_two();
+        Self {
+            data: vec![0.0; capacity],
+            mask: capacity - 1,
+            write_idx: 0,
+        }
+    }
+
+    /// Write a new sample, overwriting the oldest one.
+    #[inline(always)]
+    pub(crate) fn push(&mut self, x: f32) {
+        self.write_idx = (self.write_idx + 1) & self.mask;
+        self.data[self.write_idx] = x;
+    }
+
+    /// Sample at an integer distance behind the most recent write.
+    #[inline(always)]
+    pub(crate) fn tap(&self, i: usize) -> f32 {
+        self.data[self.write_idx.wrapping_sub(i) & self.mask]
+    }
+
+    /// Fill the buffer with silence, without reallocating.
+    pub(crate) fn clear(&mut self) {
+        self.data.iter_mut().for_each(|x| *x = 0.0);
+    }
+}
+
+/// Multi-head delay line backed by a fixed-capacity circular buffer.
+///
+/// The buffer is allocated once, so reading and writing never allocate.
 pub struct DelayLine {
-    vector: VecDeque<f32>,
-    num: usize,
+    buffer: RingBuffer,
+    sinc_table: Vec<f32>,
+    max_offset: f32,
     sr: u32,
-    head_offsets: Vec<f32>,
-    head_gains: Vec<f32>,
+    heads: Vec<Head>,
+    next_id: u32,
+    mix_div: f32,
+    feedback: f32,
+    pending_fb: f32,
+    bpm: f32,
+    beats_per_bar: f32,
+    loop_processor: Option<Box<dyn LoopProcessor + Send>>,
     interp_mode: InterpMethod,
     mix_mode: MixMethod,
 }
 
+/// Processor placed inside the feedback loop of a [`DelayLine`], e.g. a
+/// filter or a saturator for tape and analog echo designs.
+///
+/// Any `FnMut(f32) -> f32` closure is a loop processor, so existing effects
+/// can be wrapped directly:
+/// ```rust
+/// use rust_dsp_utils::effects::delay::{DelayLine, InterpMethod, MixMethod};
+/// use rust_dsp_utils::effects::filter::BlockDC;
+/// let mut delay_line = DelayLine::new(500.0, 44100, InterpMethod::Linear, MixMethod::Sum);
+/// let mut dc = BlockDC::strong(44100.0);
+/// delay_line.set_loop_processor(move |x: f32| dc.filter(x));
+/// ```
+pub trait LoopProcessor {
+    /// Process a single sample of the feedback signal.
+    fn process(&mut self, x: f32) -> f32;
+}
+
+impl<F: FnMut(f32) -> f32> LoopProcessor for F {
+    fn process(&mut self, x: f32) -> f32 {
+        self(x)
+    }
+}
+
+/// Opaque handle to a read head of a [`DelayLine`].
+///
+/// Handles are never reused, so a handle stays valid (and keeps referring to
+/// the same head) when other heads are removed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct HeadId(u32);
+
+/// State of a single read head.
+struct Head {
+    id: HeadId,
+    offset: f32,
+    target: f32,
+    gain: f32,
+    feedback: f32,
+    sync: Option<MusicalTime>,
+    enabled: bool,
+    allpass_z1: f32,
+    transition: OffsetTransition,
+    glide_coef: f32,
+    fade_from: f32,
+    fade_pos: f32,
+    fade_step: f32,
+    fade_allpass_z1: f32,
+}
+
+impl Head {
+    /// Set a new target offset in samples, jumping to it if the transition
+    /// policy is instant.
+    fn retarget(&mut self, offset: f32) {
+        self.target = offset;
+        if self.transition == OffsetTransition::Instant {
+            self.offset = offset;
+        }
+    }
+}
+
+/// Policy used by a head to move from its current offset to a new one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OffsetTransition {
+    /// Jump to the new offset immediately. This clicks on most material.
+    Instant,
+    /// Glide towards the new offset with the given time constant in
+    /// milliseconds, bending the pitch like a tape delay.
+    Glide(f32),
+    /// Crossfade from the old offset to the new one over the given time in
+    /// milliseconds, without changing the pitch. Offset changes that arrive
+    /// during a crossfade are applied once it completes.
+    Crossfade(f32),
+}
+
+/// Modifier of the length of a note value.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoteModifier {
+    Straight,
+    /// 3/2 of the straight length.
+    Dotted,
+    /// 2/3 of the straight length.
+    Triplet,
+}
+
+/// Delay time expressed in musical units, relative to the host tempo.
+/// A beat is always a quarter note.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MusicalTime {
+    /// Note value, given by the denominator of its fraction of a whole note,
+    /// e.g. `Note(8, NoteModifier::Dotted)` is a dotted eighth.
+    Note(u32, NoteModifier),
+    /// Number of beats.
+    Beats(f32),
+    /// Number of bars.
+    Bars(f32),
+}
+
+impl MusicalTime {
+    /// Length in beats (quarter notes).
+    /// # Parameters
+    /// - beats_per_bar: number of beats in a bar, e.g. 3 for 3/4 time
+    pub fn to_beats(&self, beats_per_bar: f32) -> f32 {
+        match *self {
+            MusicalTime::Note(denominator, modifier) => {
+                let straight = 4.0 / denominator.max(1) as f32;
+                match modifier {
+                    NoteModifier::Straight => straight,
+                    NoteModifier::Dotted => straight * 1.5,
+                    NoteModifier::Triplet => straight * 2.0 / 3.0,
+                }
+            },
+            MusicalTime::Beats(beats) => beats,
+            MusicalTime::Bars(bars) => bars * beats_per_bar,
+        }
+    }
+
+    /// Length in milliseconds.
+    /// # Parameters
+    /// - bpm: tempo in beats per minute
+    /// - beats_per_bar: number of beats in a bar, e.g. 3 for 3/4 time
+    pub fn to_ms(&self, bpm: f32, beats_per_bar: f32) -> f32 {
+        self.to_beats(beats_per_bar) * 60_000.0 / bpm
+    }
+}
+
+/// Method used to read between samples, when a head offset is not an
+/// integer amount of samples.
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum InterpMethod {
+    /// Round towards the write head.
     Truncate,
+    /// Round to the closest sample.
     NearestNeighbor,
+    /// Linear interpolation between the two closest samples. Plays back at
+    /// unity gain, the head gain is ignored.
     Linear,
+    /// 4-point cubic Hermite (Catmull-Rom) interpolation.
+    Hermite,
+    /// 4-point, 3rd order Lagrange interpolation.
+    Lagrange,
+    /// 1st order Thiran allpass interpolation. Flat magnitude response, but
+    /// keeps state, so it is best suited to slowly modulated offsets.
+    /// Offsets below 0.5 samples fall back to Lagrange interpolation.
+    Thiran,
+    /// Blackman-windowed sinc interpolation, with `2*SINC_HALF_TAPS` taps.
+    /// Offsets below `SINC_HALF_TAPS - 1` (7) samples, where the kernel would
+    /// reach past the write head, fall back to Lagrange interpolation.
+    Sinc,
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum MixMethod {
     Sum,
     Sqrt,
@@ -32,12 +236,23 @@ impl DelayLine {
     /// - interp: interpolation method
     pub fn new(size: f32, sr: u32, interp: InterpMethod, mix: MixMethod) -> Self {
         let num = (size/1000.0 * sr as f32) as usize + 1;
+        let sinc_table = match interp {
+            InterpMethod::Sinc => sinc_table(),
+            _ => Vec::new(),
+        };
         Self {
-            vector: VecDeque::from(vec![0.0; num]),
-            num: num,
+            buffer: RingBuffer::new(num + INTERP_MARGIN),
+            sinc_table: sinc_table,
+            max_offset: (num - 1) as f32,
             sr: sr,
-            head_offsets: Vec::new(),
-            head_gains: Vec::new(),
+            heads: Vec::new(),
+            next_id: 0,
+            mix_div: 1.0,
+            feedback: 0.0,
+            pending_fb: 0.0,
+            bpm: 120.0,
+            beats_per_bar: 4.0,
+            loop_processor: None,
             interp_mode: interp,
             mix_mode: mix,
         }
@@ -48,77 +263,450 @@ impl DelayLine {
     /// - offset: distance in milliseconds (smaller or equal to delay line size)
     /// - gain: gain at which the delay line is played back
     /// # Returns
-    /// - index of the head
-    pub fn add_head(&mut self, offset: f32, gain: f32) -> usize {
-        self.head_offsets.push(offset/1000.0 * self.sr as f32);
-        self.head_gains.push(gain);
-        self.head_offsets.len() - 1
+    /// - handle of the head, valid until the head is removed
+    pub fn add_head(&mut self, offset: f32, gain: f32) -> HeadId {
+        let id = HeadId(self.next_id);
+        self.next_id += 1;
+        let offset = self.ms_to_samples(offset);
+        self.heads.push(Head {
+            id: id,
+            offset: offset,
+            target: offset,
+            gain: gain,
+            feedback: 0.0,
+            sync: None,
+            enabled: true,
+            allpass_z1: 0.0,
+            transition: OffsetTransition::Instant,
+            glide_coef: 1.0,
+            fade_from: offset,
+            fade_pos: 1.0,
+            fade_step: 1.0,
+            fade_allpass_z1: 0.0,
+        });
+        self.update_mix_div();
+        id
     }
 
     /// remove a read head
     /// # Parameters
-    /// - index: index of head to remove
+    /// - id: handle of the head to remove
     /// # Returns
     /// - boolean representing wether the head existed in the first place.
-    pub fn remove_head(&mut self, index: usize) -> bool {
-        if index < self.head_offsets.len() {
-            self.head_offsets.remove(index);
-            self.head_gains.remove(index);
-            true
-        } else {
-            false
+    pub fn remove_head(&mut self, id: HeadId) -> bool {
+        match self.heads.iter().position(|h| h.id == id) {
+            Some(index) => {
+                self.heads.remove(index);
+                self.update_mix_div();
+                true
+            },
+            None => false,
         }
     }
 
-    /// changes the offset of one of the heads.
+    /// changes the offset of one of the heads. The head moves to the new
+    /// offset according to its transition policy, see `set_transition`.
     /// # Parameters
-    /// - index: index of the head to be changed
-    /// - offset: new offset for the head
+    /// - id: handle of the head to be changed
+    /// - offset: new offset for the head, in milliseconds
     /// # Returns
     /// - boolean representing wether the chosen head exists.
-    /// # Side-effects
-    /// The vector of heads is shifted, thus all indexes greater than the one
-    /// removed are shifted with it.
-    /// TODO: perhaps heads should be stored in a dictionary instead.
-    pub fn set_offset(&mut self, index: usize, offset: f32) -> bool {
-        if index < self.head_offsets.len() {
-            self.head_offsets[index] = offset/1000.0 * self.sr as f32;
-            true
-        } else {
-            false
+    ///
+    /// If the head was synced to the tempo, it stops being synced.
+    pub fn set_offset(&mut self, id: HeadId, offset: f32) -> bool {
+        let offset = self.ms_to_samples(offset);
+        match self.head_mut(id) {
+            Some(head) => {
+                head.sync = None;
+                head.retarget(offset);
+                true
+            },
+            None => false,
         }
     }
 
+    /// syncs the offset of one of the heads to the tempo. The offset is
+    /// recomputed whenever the tempo or time signature changes, and moves
+    /// according to the transition policy of the head.
+    /// # Parameters
+    /// - id: handle of the head to be changed
+    /// - time: new offset for the head, in musical units
+    /// # Returns
+    /// - boolean representing wether the chosen head exists.
+    pub fn set_synced_offset(&mut self, id: HeadId, time: MusicalTime) -> bool {
+        let offset = self.ms_to_samples(time.to_ms(self.bpm, self.beats_per_bar));
+        match self.head_mut(id) {
+            Some(head) => {
+                head.sync = Some(time);
+                head.retarget(offset);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// musical offset of a head, or `None` if the head does not exist or is
+    /// not synced to the tempo.
+    pub fn synced_offset(&self, id: HeadId) -> Option<MusicalTime> {
+        self.head(id).and_then(|h| h.sync)
+    }
+
+    /// changes the tempo, updating all heads synced to it.
+    /// # Parameters
+    /// - bpm: tempo in beats per minute, as reported by the host
+    pub fn set_tempo(&mut self, bpm: f32) {
+        if bpm > 0.0 && bpm != self.bpm {
+            self.bpm = bpm;
+            self.update_synced_offsets();
+        }
+    }
+
+    /// changes the time signature, updating all heads synced to it.
+    /// # Parameters
+    /// - beats_per_bar: number of beats in a bar, e.g. 3 for 3/4 time
+    pub fn set_time_signature(&mut self, beats_per_bar: f32) {
+        if beats_per_bar > 0.0 && beats_per_bar != self.beats_per_bar {
+            self.beats_per_bar = beats_per_bar;
+            self.update_synced_offsets();
+        }
+    }
+
+    /// changes how one of the heads reacts to offset changes. Heads are
+    /// created with `OffsetTransition::Instant`.
+    /// # Parameters
+    /// - id: handle of the head to be changed
+    /// - transition: new transition policy
+    /// # Returns
+    /// - boolean representing wether the chosen head exists.
+    pub fn set_transition(&mut self, id: HeadId, transition: OffsetTransition) -> bool {
+        let samples_per_ms = self.sr as f32 / 1000.0;
+        match self.head_mut(id) {
+            Some(head) => {
+                match transition {
+                    OffsetTransition::Instant => {
+                        head.offset = head.target;
+                        head.fade_pos = 1.0;
+                    },
+                    OffsetTransition::Glide(time) =>
+                        head.glide_coef = 1.0 - (-1.0 / (time*samples_per_ms).max(1.0)).exp(),
+                    OffsetTransition::Crossfade(time) =>
+                        head.fade_step = 1.0 / (time*samples_per_ms).max(1.0),
+                }
+                head.transition = transition;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// changes the gain of one of the heads.
+    /// # Parameters
+    /// - id: handle of the head to be changed
+    /// - gain: new gain for the head
+    /// # Returns
+    /// - boolean representing wether the chosen head exists.
+    pub fn set_gain(&mut self, id: HeadId, gain: f32) -> bool {
+        match self.head_mut(id) {
+            Some(head) => {
+                head.gain = gain;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// changes the feedback of one of the heads, i.e. how much of the head's
+    /// output is fed back into the delay line. Independent of the head gain.
+    /// # Parameters
+    /// - id: handle of the head to be changed
+    /// - feedback: new feedback gain for the head
+    /// # Returns
+    /// - boolean representing wether the chosen head exists.
+    pub fn set_head_feedback(&mut self, id: HeadId, feedback: f32) -> bool {
+        match self.head_mut(id) {
+            Some(head) => {
+                head.feedback = feedback;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// changes the global feedback, i.e. how much of the mixed output is fed
+    /// back into the delay line. Adds up with the feedback of each head.
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback;
+    }
+
+    /// places a processor inside the feedback loop. The processor runs on
+    /// every sample, even when all feedback gains are zero, so that filters
+    /// inside the loop keep a continuous state.
+    pub fn set_loop_processor<P: LoopProcessor + Send + 'static>(&mut self, processor: P) {
+        self.loop_processor = Some(Box::new(processor));
+    }
+
+    /// removes the processor from the feedback loop, if any.
+    pub fn clear_loop_processor(&mut self) {
+        self.loop_processor = None;
+    }
+
+    /// enables or disables one of the heads. Disabled heads are not read, and
+    /// do not count towards the normalization of the mix.
+    /// # Parameters
+    /// - id: handle of the head to be changed
+    /// - enabled: new state of the head
+    /// # Returns
+    /// - boolean representing wether the chosen head exists.
+    pub fn set_enabled(&mut self, id: HeadId, enabled: bool) -> bool {
+        match self.head_mut(id) {
+            Some(head) => {
+                head.enabled = enabled;
+                self.update_mix_div();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// offset of a head in milliseconds, or `None` if the head does not exist.
+    /// This is the last offset that was set, which the head might still be
+    /// transitioning to.
+    pub fn offset(&self, id: HeadId) -> Option<f32> {
+        self.head(id).map(|h| h.target / self.sr as f32 * 1000.0)
+    }
+
+    /// gain of a head, or `None` if the head does not exist.
+    pub fn gain(&self, id: HeadId) -> Option<f32> {
+        self.head(id).map(|h| h.gain)
+    }
+
+    /// state of a head, or `None` if the head does not exist.
+    pub fn is_enabled(&self, id: HeadId) -> Option<bool> {
+        self.head(id).map(|h| h.enabled)
+    }
+
+    /// iterate over the handles of all enabled heads, in the order they were
+    /// added.
+    pub fn active_heads(&self) -> impl Iterator<Item = HeadId> + '_ {
+        self.heads.iter().filter(|h| h.enabled).map(|h| h.id)
+    }
+
     /// write a new value into the delay line and read from all active read heads
     /// # Parameters
-    /// - write: input to write
+    /// - write: input to write, the feedback signal is added to it
     /// # Returns
     /// - mixed outputs from active heads
-    pub fn read_write(&mut self, write: f32) -> f32{
-        // Step 1: read previous values from read heads
-        let accumulator = self.head_offsets.iter()
-            .zip(self.head_gains.iter())
-            .map(|(a, b)| {println!("{}", a); match self.interp_mode {
-                    InterpMethod::Truncate => 
-                        self.vector[*a as usize]*b,
-                    InterpMethod::NearestNeighbor => 
-                        self.vector[(*a).round() as usize]*b,
-                    InterpMethod::Linear => {
-                        let i = (*a).floor() as usize;
-                        let x = *a - i as f32;
-                        math::x_fade(self.vector[i], x, self.vector[i + 1])
-                    },
-                }})
-            .sum::<f32>() / match self.mix_mode {
-                MixMethod::Sum => 1.0,
-                MixMethod::Sqrt => (self.head_offsets.len() as f32).sqrt(),
-                MixMethod::Average => self.head_offsets.len() as f32,
-            };
+    pub fn read_write(&mut self, write: f32) -> f32 {
+        let out = self.read();
+        self.write(write);
+        return out;
+    }
 
-        // Step 2: write new value and shift deque
-        self.vector.push_front(write);
-        self.vector.pop_back();
+    /// read from all active read heads, without advancing the delay line.
+    /// This is the first half of `read_write`, for when the value to write
+    /// depends on the output, e.g. in cross-feedback networks. It must be
+    /// followed by exactly one call to `write`.
+    /// # Returns
+    /// - mixed outputs from active heads
+    pub fn read(&mut self) -> f32 {
+        let mut accumulator = 0.0;
+        let mut fb_accumulator = 0.0;
+        for head in self.heads.iter_mut().filter(|h| h.enabled) {
+            let x = read_head(&self.buffer, &self.interp_mode, &self.sinc_table, head);
+            // Linear mode has always ignored the head gain, keep its output
+            let gain = if self.interp_mode == InterpMethod::Linear { 1.0 } else { head.gain };
+            accumulator += x * gain;
+            fb_accumulator += x * head.feedback;
+        }
+        let out = accumulator / self.mix_div;
+
+        // process the feedback signal, it is added to the next write
+        let mut fb = fb_accumulator + out*self.feedback;
+        if let Some(processor) = self.loop_processor.as_mut() {
+            fb = processor.process(fb);
+        }
+        self.pending_fb = fb;
+
+        return out;
+    }
+
+    /// write a new value into the delay line, advancing it by one sample.
+    /// This is the second half of `read_write`, see `read`.
+    /// # Parameters
+    /// - write: input to write, the feedback signal is added to it
+    pub fn write(&mut self, write: f32) {
+        self.buffer.push(write + self.pending_fb);
+        self.pending_fb = 0.0;
+    }
+
+    fn head(&self, id: HeadId) -> Option<&Head> {
+        self.heads.iter().find(|h| h.id == id)
+    }
+
+    fn head_mut(&mut self, id: HeadId) -> Option<&mut Head> {
+        self.heads.iter_mut().find(|h| h.id == id)
+    }
+
+    /// Recompute the offsets of the heads synced to the tempo.
+    fn update_synced_offsets(&mut self) {
+        let samples_per_ms = self.sr as f32 / 1000.0;
+        let (bpm, beats_per_bar, max_offset) = (self.bpm, self.beats_per_bar, self.max_offset);
+        for head in self.heads.iter_mut() {
+            if let Some(time) = head.sync {
+                let offset = (time.to_ms(bpm, beats_per_bar)*samples_per_ms).clamp(0.0, max_offset);
+                head.retarget(offset);
+            }
+        }
+    }
+
+    /// Convert milliseconds to samples, clamped to the size of the delay line.
+    fn ms_to_samples(&self, ms: f32) -> f32 {
+        (ms/1000.0 * self.sr as f32).clamp(0.0, self.max_offset)
+    }
+
+    /// Precompute the normalization of the head mix, so that it is not
+    /// recomputed on every sample.
+    fn update_mix_div(&mut self) {
+        let heads = self.heads.iter().filter(|h| h.enabled).count().max(1) as f32;
+        self.mix_div = match self.mix_mode {
+            MixMethod::Sum => 1.0,
+            MixMethod::Sqrt => heads.sqrt(),
+            MixMethod::Average => heads,
+        };
+    }
+}
+
+/// Advance the offset transition of a head by one sample, and read from it.
+#[inline(always)]
+fn read_head(buffer: &RingBuffer, interp: &InterpMethod, sinc_table: &[f32], head: &mut Head) -> f32 {
+    match head.transition {
+        OffsetTransition::Instant =>
+            read(buffer, interp, sinc_table, head.offset, &mut head.allpass_z1),
+        OffsetTransition::Glide(_) => {
+            head.offset += (head.target - head.offset)*head.glide_coef;
+            if (head.target - head.offset).abs() < 1e-4 {
+                head.offset = head.target;
+            }
+            read(buffer, interp, sinc_table, head.offset, &mut head.allpass_z1)
+        },
+        OffsetTransition::Crossfade(_) => {
+            // start a new crossfade only once the previous one is complete
+            if head.fade_pos >= 1.0 && head.target != head.offset {
+                head.fade_from = head.offset;
+                head.offset = head.target;
+                head.fade_pos = 0.0;
+                head.fade_allpass_z1 = head.allpass_z1;
+            }
+            let new = read(buffer, interp, sinc_table, head.offset, &mut head.allpass_z1);
+            if head.fade_pos < 1.0 {
+                let old = read(buffer, interp, sinc_table, head.fade_from, &mut head.fade_allpass_z1);
+                let ret = math::x_fade(old, head.fade_pos, new);
+                head.fade_pos += head.fade_step;
+                ret
+            } else {
+                new
+            }
+        },
+    }
+}
+
+/// Read the sample `offset` samples behind the most recent write, using the
+/// given interpolation method. `allpass_z1` is the state of the Thiran
+/// interpolator for this read position.
+///
+/// Neighbours that would lie in front of the write head are replaced by the
+/// most recent sample, neighbours past the end of the line are covered by
+/// `INTERP_MARGIN`.
+#[inline(always)]
+pub(crate) fn read(buffer: &RingBuffer, interp: &InterpMethod, sinc_table: &[f32], offset: f32, allpass_z1: &mut f32) -> f32 {
+    match interp {
+        InterpMethod::Truncate =>
+            buffer.tap(offset as usize),
+        InterpMethod::NearestNeighbor =>
+            buffer.tap(offset.round() as usize),
+        InterpMethod::Linear => {
+            let i = offset.floor() as usize;
+            let x = offset - i as f32;
+            math::x_fade(buffer.tap(i), x, buffer.tap(i + 1))
+        },
+        InterpMethod::Hermite => {
+            let i = offset.floor() as usize;
+            let x = offset - i as f32;
+            let (y_m1, y_0, y_1, y_2) = (buffer.tap(i.saturating_sub(1)), buffer.tap(i), buffer.tap(i + 1), buffer.tap(i + 2));
+            let c1 = 0.5*(y_1 - y_m1);
+            let c2 = y_m1 - 2.5*y_0 + 2.0*y_1 - 0.5*y_2;
+            let c3 = 0.5*(y_2 - y_m1) + 1.5*(y_0 - y_1);
+            ((c3*x + c2)*x + c1)*x + y_0
+        },
+        InterpMethod::Lagrange =>
+            lagrange(buffer, offset),
+        InterpMethod::Thiran => {
+            // keep the fractional delay in [0.5, 1.5), where the allpass
+            // coefficient is best behaved. Closer to the write head, use
+            // Lagrange, and keep the allpass state in sync with it.
+            if offset < 0.5 {
+                *allpass_z1 = lagrange(buffer, offset);
+                return *allpass_z1;
+            }
+            let i = (offset - 0.5).floor() as usize;
+            let d = offset - i as f32;
+            let a = (1.0 - d) / (1.0 + d);
+            let y = a*(buffer.tap(i) - *allpass_z1) + buffer.tap(i + 1);
+            *allpass_z1 = y;
+            y
+        },
+        InterpMethod::Sinc => {
+            let i = offset.floor() as usize;
+            // the kernel would need samples newer than the write head
+            if i + 1 < SINC_HALF_TAPS {
+                return lagrange(buffer, offset);
+            }
+            let pos = (offset - i as f32) * SINC_PHASES as f32;
+            let phase = (pos as usize).min(SINC_PHASES - 1);
+            let x = pos - phase as f32;
+            let row_a = &sinc_table[phase*2*SINC_HALF_TAPS..(phase + 1)*2*SINC_HALF_TAPS];
+            let row_b = &sinc_table[(phase + 1)*2*SINC_HALF_TAPS..(phase + 2)*2*SINC_HALF_TAPS];
+            let mut acc = 0.0;
+            for k in 0..2*SINC_HALF_TAPS {
+                // tap k is at distance i + k + 1 - SINC_HALF_TAPS
+                let sample = buffer.tap(i + k + 1 - SINC_HALF_TAPS);
+                acc += sample * math::x_fade(row_a[k], x, row_b[k]);
+            }
+            acc
+        },
+    }
+}
 
-        return accumulator;
+/// 4-point, 3rd order Lagrange interpolation. Within a sample of the write
+/// head, the 4 points are taken behind the offset instead of around it.
+fn lagrange(buffer: &RingBuffer, offset: f32) -> f32 {
+    let i = (offset.floor() as usize).saturating_sub(1);
+    let x = offset - i as f32;
+    let (y_0, y_1, y_2, y_3) = (buffer.tap(i), buffer.tap(i + 1), buffer.tap(i + 2), buffer.tap(i + 3));
+    let (d_0, d_1, d_2, d_3) = (x, x - 1.0, x - 2.0, x - 3.0);
+    - y_0*d_1*d_2*d_3/6.0
+    + y_1*d_0*d_2*d_3/2.0
+    - y_2*d_0*d_1*d_3/2.0
+    + y_3*d_0*d_1*d_2/6.0
+}
+
+/// Tabulate the windowed-sinc kernel for `SINC_PHASES + 1` fractional
+/// positions in [0, 1]. Each row is normalized to unity gain at DC.
+fn sinc_table() -> Vec<f32> {
+    let taps = 2*SINC_HALF_TAPS;
+    let mut table = vec![0.0; (SINC_PHASES + 1)*taps];
+    for phase in 0..=SINC_PHASES {
+        let frac = phase as f32 / SINC_PHASES as f32;
+        let row = &mut table[phase*taps..(phase + 1)*taps];
+        for (k, h) in row.iter_mut().enumerate() {
+            let t = k as f32 + 1.0 - SINC_HALF_TAPS as f32 - frac;
+            let sinc = if t == 0.0 { 1.0 } else { (consts::PI*t).sin() / (consts::PI*t) };
+            let w = t / SINC_HALF_TAPS as f32;
+            let window = 0.42 + 0.5*(consts::PI*w).cos() + 0.08*(consts::TAU*w).cos();
+            *h = sinc * window;
+        }
+        let sum: f32 = row.iter().sum();
+        row.iter_mut().for_each(|h| *h /= sum);
     }
-}
\ No newline at end of file
+    return table;
+}
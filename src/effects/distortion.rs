@@ -1,3 +1,5 @@
+use std::f64::consts;
+
 /// Applies mu-law companding to a signal
 /// # Parameters
 /// + `x`: input signal
@@ -26,4 +28,285 @@ pub fn inv_mu_law(x: f32, amount: f32) -> f32 {
 pub fn var_clip(x: f32, hardness: f32) -> f32 {
     let k = 1.0 - hardness.clamp(0.0, 0.9999);
     x.abs() / (x.abs().powf(1.0 / k) + 0.1).powf(k) * x.signum()
-}
\ No newline at end of file
+}
+
+/// Waveshapers with antialiased versions, see `Adaa1` and `Adaa2`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Waveshaper {
+    /// Clamps the signal between -1.0 and 1.0.
+    HardClip,
+    Tanh,
+    /// `mu_law` with the given amount.
+    MuLaw(f32),
+    /// `var_clip` with the given hardness.
+    VarClip(f32),
+}
+
+impl Waveshaper {
+    /// Applies the waveshaper to a sample, without antialiasing.
+    pub fn apply(self, x: f32) -> f32 {
+        match self {
+            Waveshaper::HardClip => x.clamp(-1.0, 1.0),
+            Waveshaper::Tanh => x.tanh(),
+            Waveshaper::MuLaw(amount) => mu_law(x, amount),
+            Waveshaper::VarClip(hardness) => var_clip(x, hardness),
+        }
+    }
+
+    fn f(self, x: f64) -> f64 {
+        self.apply(x as f32) as f64
+    }
+
+    /// First antiderivative, or `None` for `VarClip`, which has no closed
+    /// form. All the waveshapers are odd, so it is even.
+    fn ad1(self, x: f64) -> Option<f64> {
+        let a = x.abs();
+        let ad1 = match self {
+            Waveshaper::HardClip => if a <= 1.0 { 0.5*a*a } else { a - 0.5 },
+            // ln(cosh(x)), without overflow
+            Waveshaper::Tanh => a + (-2.0*a).exp().ln_1p() - consts::LN_2,
+            Waveshaper::MuLaw(amount) => {
+                let (mu, ln_mu) = mu_law_params(amount);
+                let u = 1.0 + mu*a;
+                (u*u.ln() - mu*a)/(mu*ln_mu)
+            }
+            Waveshaper::VarClip(_) => return None,
+        };
+        return Some(ad1);
+    }
+
+    /// Second antiderivative, or `None` for `VarClip`, which has no closed
+    /// form. It is odd.
+    fn ad2(self, x: f64) -> Option<f64> {
+        let a = x.abs();
+        let ad2 = match self {
+            Waveshaper::HardClip => if a <= 1.0 { a*a*a/6.0 } else { 0.5*a*a - 0.5*a + 1.0/6.0 },
+            Waveshaper::Tanh => {
+                0.5*a*a - a*consts::LN_2 + 0.5*dilog(-(-2.0*a).exp()) + consts::PI*consts::PI/24.0
+            }
+            Waveshaper::MuLaw(amount) => {
+                let (mu, ln_mu) = mu_law_params(amount);
+                let u = 1.0 + mu*a;
+                ((0.5*u*u*u.ln() - 0.25*u*u + 0.25)/mu - 0.5*mu*a*a)/(mu*ln_mu)
+            }
+            Waveshaper::VarClip(_) => return None,
+        };
+        return Some(ad2.copysign(x));
+    }
+}
+
+/// `mu` and `ln(1 + mu)` of `mu_law`.
+fn mu_law_params(amount: f32) -> (f64, f64) {
+    let mu = 1.0/(1.0 - amount.clamp(0.0, 0.9999) as f64);
+    (mu, mu.ln_1p())
+}
+
+/// Dilogarithm `Li2(z)` for `z` in [-1, 0].
+fn dilog(z: f64) -> f64 {
+    // Li2(z) = -Li2(z/(z - 1)) - ln(1 - z)^2/2, and the series of
+    // Li2(w) = sum(w^k/k^2) converges quickly for w in [0, 0.5]
+    let w = z/(z - 1.0);
+    let mut sum = 0.0;
+    let mut w_k = w;
+    let mut k = 1.0;
+    while w_k > 1e-17 {
+        sum += w_k/(k*k);
+        w_k *= w;
+        k += 1.0;
+    }
+    -sum - 0.5*(-z).ln_1p().powi(2)
+}
+
+/// Nodes and weights of the 6-point Gauss-Legendre quadrature over [0, 1].
+const GAUSS_LEGENDRE: [(f64, f64); 6] = [
+    (0.033_765_242_898_423_99, 0.085_662_246_189_585_17),
+    (0.169_395_306_766_867_74, 0.180_380_786_524_069_3),
+    (0.380_690_406_958_401_5, 0.233_956_967_286_345_5),
+    (0.619_309_593_041_598_5, 0.233_956_967_286_345_5),
+    (0.830_604_693_233_132_3, 0.180_380_786_524_069_3),
+    (0.966_234_757_101_576, 0.085_662_246_189_585_17),
+];
+
+/// Inputs closer than this are ill-conditioned for the divided differences
+/// of the antiderivatives.
+const ADAA_TOLERANCE: f64 = 1e-5;
+
+/// Waveshaper with first-order antiderivative antialiasing (ADAA), after
+/// Parker et al., "Reducing the Aliasing of Nonlinear Waveshaping Using
+/// Continuous-Time Convolution" (DAFx 2016).
+///
+/// The output is the mean of the waveshaper over the segment between two
+/// consecutive inputs, `(F1(x[n]) - F1(x[n-1]))/(x[n] - x[n-1])` with `F1` the
+/// antiderivative of the waveshaper. This attenuates the aliasing, and delays
+/// the signal by half a sample. `VarClip` has no closed-form antiderivative,
+/// so its mean is computed by quadrature.
+pub struct Adaa1 {
+    shaper: Waveshaper,
+    x_z1: f64,
+    ad1_z1: f64,
+}
+
+impl Adaa1 {
+    /// Create a new antialiased waveshaper.
+    pub fn new(shaper: Waveshaper) -> Self {
+        let mut ret = Self {
+            shaper: shaper,
+            x_z1: 0.0,
+            ad1_z1: 0.0,
+        };
+        ret.reset();
+        return ret;
+    }
+
+    /// Change the waveshaper, keeping the state.
+    pub fn set_shaper(&mut self, shaper: Waveshaper) {
+        self.shaper = shaper;
+        self.ad1_z1 = shaper.ad1(self.x_z1).unwrap_or(0.0);
+    }
+
+    /// Process a sample.
+    pub fn process(&mut self, input: f32) -> f32 {
+        let x = input as f64;
+        let x_z1 = self.x_z1;
+        self.x_z1 = x;
+        let ad1 = match self.shaper.ad1(x) {
+            Some(ad1) => ad1,
+            None => {
+                let d = x - x_z1;
+                return GAUSS_LEGENDRE.iter()
+                    .map(|(u, w)| w*self.shaper.f(x_z1 + u*d))
+                    .sum::<f64>() as f32;
+            }
+        };
+        let ad1_z1 = self.ad1_z1;
+        self.ad1_z1 = ad1;
+        if (x - x_z1).abs() < ADAA_TOLERANCE {
+            return self.shaper.f(0.5*(x + x_z1)) as f32;
+        }
+        return ((ad1 - ad1_z1)/(x - x_z1)) as f32;
+    }
+
+    /// Reset the state to a silent input.
+    pub fn reset(&mut self) {
+        self.x_z1 = 0.0;
+        self.ad1_z1 = self.shaper.ad1(0.0).unwrap_or(0.0);
+    }
+}
+
+/// Waveshaper with second-order antiderivative antialiasing (ADAA), after
+/// Bilbao et al., "Antiderivative Antialiasing for Memoryless
+/// Nonlinearities" (IEEE SPL 2017).
+///
+/// The output is the second divided difference of the second antiderivative
+/// of the waveshaper over the last three inputs, i.e. the waveshaper
+/// averaged with a triangular kernel. The aliasing is attenuated more than
+/// with `Adaa1`, and the signal is delayed by one sample. `VarClip` has no
+/// closed-form antiderivatives, so its average is computed by quadrature.
+pub struct Adaa2 {
+    shaper: Waveshaper,
+    x_z1: f64,
+    x_z2: f64,
+    ad2_z1: f64,
+    diff_z1: f64,
+}
+
+impl Adaa2 {
+    /// Create a new antialiased waveshaper.
+    pub fn new(shaper: Waveshaper) -> Self {
+        let mut ret = Self {
+            shaper: shaper,
+            x_z1: 0.0,
+            x_z2: 0.0,
+            ad2_z1: 0.0,
+            diff_z1: 0.0,
+        };
+        ret.reset();
+        return ret;
+    }
+
+    /// Change the waveshaper, keeping the state.
+    pub fn set_shaper(&mut self, shaper: Waveshaper) {
+        self.shaper = shaper;
+        if let Some((ad2_z1, diff_z1)) = self.state(self.x_z1, self.x_z2) {
+            self.ad2_z1 = ad2_z1;
+            self.diff_z1 = diff_z1;
+        }
+    }
+
+    /// Process a sample.
+    pub fn process(&mut self, input: f32) -> f32 {
+        let (x, x_z1, x_z2) = (input as f64, self.x_z1, self.x_z2);
+        self.x_z2 = x_z1;
+        self.x_z1 = x;
+        let y = match self.closed_form(x, x_z1, x_z2) {
+            Some(y) => y,
+            None => self.quadrature(x, x_z1, x_z2),
+        };
+        return y as f32;
+    }
+
+    /// Reset the state to a silent input.
+    pub fn reset(&mut self) {
+        self.x_z1 = 0.0;
+        self.x_z2 = 0.0;
+        if let Some((ad2_z1, diff_z1)) = self.state(0.0, 0.0) {
+            self.ad2_z1 = ad2_z1;
+            self.diff_z1 = diff_z1;
+        }
+    }
+
+    /// Output from the closed-form antiderivatives, which updates their
+    /// state, or `None` if the waveshaper has none.
+    fn closed_form(&mut self, x: f64, x_z1: f64, x_z2: f64) -> Option<f64> {
+        let (ad2, ad2_z1) = (self.shaper.ad2(x)?, self.ad2_z1);
+        let diff = self.divided_diff(x, x_z1, ad2, ad2_z1)?;
+        let diff_z1 = self.diff_z1;
+        self.ad2_z1 = ad2;
+        self.diff_z1 = diff;
+        if (x - x_z2).abs() >= ADAA_TOLERANCE {
+            return Some(2.0*(diff - diff_z1)/(x - x_z2));
+        }
+        // x[n] and x[n-2] are too close: average over the segment between
+        // their mean and x[n-1] instead
+        let x_bar = 0.5*(x + x_z2);
+        let delta = x_bar - x_z1;
+        if delta.abs() < ADAA_TOLERANCE {
+            return Some(self.shaper.f(0.5*(x_bar + x_z1)));
+        }
+        return Some(2.0/delta*(self.shaper.ad1(x_bar)? + (ad2_z1 - self.shaper.ad2(x_bar)?)/delta));
+    }
+
+    /// State of the closed-form antiderivatives after two inputs, i.e. the
+    /// second antiderivative at the last one and the divided difference, or
+    /// `None` if the waveshaper has none.
+    fn state(&self, x_z1: f64, x_z2: f64) -> Option<(f64, f64)> {
+        let ad2_z1 = self.shaper.ad2(x_z1)?;
+        let diff_z1 = self.divided_diff(x_z1, x_z2, ad2_z1, self.shaper.ad2(x_z2)?)?;
+        return Some((ad2_z1, diff_z1));
+    }
+
+    /// First divided difference of the second antiderivative between two
+    /// inputs.
+    fn divided_diff(&self, x: f64, x_z1: f64, ad2: f64, ad2_z1: f64) -> Option<f64> {
+        if (x - x_z1).abs() < ADAA_TOLERANCE {
+            return self.shaper.ad1(0.5*(x + x_z1));
+        }
+        return Some((ad2 - ad2_z1)/(x - x_z1));
+    }
+
+    /// Average of the waveshaper weighted by the triangular kernel between
+    /// the three inputs, rising from the lowest to the middle one, and
+    /// falling to the highest one.
+    fn quadrature(&self, x0: f64, x1: f64, x2: f64) -> f64 {
+        let mut x = [x0, x1, x2];
+        x.sort_by(f64::total_cmp);
+        let [a, b, c] = x;
+        if c - a < ADAA_TOLERANCE {
+            return self.shaper.f(b);
+        }
+        let (rise, fall) = GAUSS_LEGENDRE.iter().fold((0.0, 0.0), |(rise, fall), (u, w)| {
+            (rise + 2.0*u*w*self.shaper.f(a + u*(b - a)), fall + 2.0*u*w*self.shaper.f(c - u*(c - b)))
+        });
+        return ((b - a)*rise + (c - b)*fall)/(c - a);
+    }
+}
@@ -0,0 +1,297 @@
+use std::f32::consts;
+
+use crate::effects::delay::RingBuffer;
+use crate::utils::chaos::Rng;
+use crate::utils::math;
+
+/// Longest delay line at full room size, in milliseconds.
+const MAX_LENGTH_MS: f32 = 100.0;
+
+/// Shortest delay line at full room size, in milliseconds.
+const MIN_LENGTH_MS: f32 = 25.0;
+
+/// Maximum modulation depth, in milliseconds.
+const MAX_MOD_DEPTH_MS: f32 = 4.0;
+
+/// Number of delay lines in a feedback delay network.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FdnSize {
+    Four,
+    Eight,
+    Sixteen,
+}
+
+impl FdnSize {
+    fn lines(&self) -> usize {
+        match self {
+            FdnSize::Four => 4,
+            FdnSize::Eight => 8,
+            FdnSize::Sixteen => 16,
+        }
+    }
+}
+
+/// Orthogonal matrix mixing the outputs of the delay lines back into their
+/// inputs.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MixMatrix {
+    /// Householder reflection, cheapest and least diffuse.
+    Householder,
+    /// Normalized Hadamard matrix, computed with a fast Walsh-Hadamard
+    /// transform. Maximally diffuse.
+    Hadamard,
+    /// Random orthogonal matrix, generated from the given seed.
+    RandomOrthogonal(u64),
+}
+
+/// Splits the signal in three bands with one-pole filters and applies a
+/// separate gain to each, so that each band decays at its own rate.
+struct BandDamping {
+    lp_low: f32,
+    lp_high: f32,
+    gain_low: f32,
+    gain_mid: f32,
+    gain_high: f32,
+}
+
+impl BandDamping {
+    #[inline(always)]
+    fn filter(&mut self, x: f32, coef_low: f32, coef_high: f32) -> f32 {
+        self.lp_low += (x - self.lp_low)*coef_low;
+        self.lp_high += (x - self.lp_high)*coef_high;
+        let low = self.lp_low;
+        let high = x - self.lp_high;
+        let mid = x - low - high;
+        low*self.gain_low + mid*self.gain_mid + high*self.gain_high
+    }
+}
+
+/// A single delay line of the network.
+struct FdnLine {
+    buffer: RingBuffer,
+    length: f32,
+    lfo_phase: f32,
+    lfo_rate: f32,
+    damping: BandDamping,
+}
+
+/// Feedback delay network reverb, with 4, 8 or 16 modulated delay lines,
+/// a selectable mixing matrix and a decay time set separately in three
+/// frequency bands.
+///
+/// Outputs only the wet signal.
+pub struct FdnReverb {
+    lines: Vec<FdnLine>,
+    matrix: MixMatrix,
+    random_matrix: Vec<f32>,
+    sr: f32,
+    room_size: f32,
+    rt60: (f32, f32, f32),
+    coef_low: f32,
+    coef_high: f32,
+    mod_depth: f32,
+}
+
+impl FdnReverb {
+    /// Create a new reverb.
+    /// # Parameters
+    /// - sr: sample rate in hertz
+    /// - size: number of delay lines
+    /// - matrix: feedback matrix
+    pub fn new(sr: u32, size: FdnSize, matrix: MixMatrix) -> Self {
+        let sr = sr as f32;
+        let n = size.lines();
+        let capacity = ((MAX_LENGTH_MS + 2.0*MAX_MOD_DEPTH_MS)/1000.0*sr) as usize + 2;
+        let lines = (0..n).map(|i| FdnLine {
+            buffer: RingBuffer::new(capacity),
+            length: 1.0,
+            lfo_phase: consts::TAU*i as f32/n as f32,
+            lfo_rate: 0.0,
+            damping: BandDamping {
+                lp_low: 0.0,
+                lp_high: 0.0,
+                gain_low: 0.0,
+                gain_mid: 0.0,
+                gain_high: 0.0,
+            },
+        }).collect();
+        let random_matrix = match matrix {
+            MixMatrix::RandomOrthogonal(seed) => random_orthogonal(n, seed),
+            _ => Vec::new(),
+        };
+        let mut ret = Self {
+            lines: lines,
+            matrix: matrix,
+            random_matrix: random_matrix,
+            sr: sr,
+            room_size: 1.0,
+            rt60: (2.0, 1.5, 0.8),
+            coef_low: 0.0,
+            coef_high: 0.0,
+            mod_depth: 0.0,
+        };
+        ret.set_crossover(300.0, 4000.0);
+        ret.set_modulation(0.5, 0.6);
+        ret.set_room_size(0.7);
+        return ret;
+    }
+
+    /// Set the room size in [0, 1], scaling the length of all delay lines.
+    /// Not meant to be modulated, as the lengths jump to the new values.
+    pub fn set_room_size(&mut self, size: f32) {
+        self.room_size = size.clamp(0.05, 1.0);
+        let n = self.lines.len();
+        let ratio = MAX_LENGTH_MS / MIN_LENGTH_MS;
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            // lengths spread exponentially, rounded to primes so that the
+            // lines share as few resonances as possible
+            let ms = MIN_LENGTH_MS * ratio.powf(i as f32 / (n - 1) as f32) * self.room_size;
+            line.length = next_prime((ms/1000.0*self.sr) as usize) as f32;
+        }
+        self.update_decay();
+    }
+
+    /// Set the time it takes for the reverb to decay by 60dB, in seconds, in
+    /// the low, mid and high bands.
+    pub fn set_decay(&mut self, low: f32, mid: f32, high: f32) {
+        self.rt60 = (low.max(0.01), mid.max(0.01), high.max(0.01));
+        self.update_decay();
+    }
+
+    /// Set the crossover frequencies between the low, mid and high decay
+    /// bands, in hertz.
+    pub fn set_crossover(&mut self, low: f32, high: f32) {
+        self.coef_low = one_pole_coef(low, self.sr);
+        self.coef_high = one_pole_coef(high.max(low), self.sr);
+    }
+
+    /// Set the modulation of the delay lines.
+    /// # Parameters
+    /// - depth: modulation depth in milliseconds
+    /// - rate: modulation rate in hertz; each line runs at a slightly
+    ///   different rate to avoid audible periodicity
+    pub fn set_modulation(&mut self, depth: f32, rate: f32) {
+        self.mod_depth = depth.clamp(0.0, MAX_MOD_DEPTH_MS)/1000.0*self.sr;
+        let n = self.lines.len() as f32;
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            line.lfo_rate = consts::TAU*rate*(1.0 + 0.5*i as f32/n)/self.sr;
+        }
+    }
+
+    /// Process a mono sample.
+    pub fn process_mono(&mut self, x: f32) -> f32 {
+        let (l, r) = self.process(x, x);
+        return 0.5*(l + r);
+    }
+
+    /// Process a stereo sample. Each input feeds half of the lines, and each
+    /// output is taken from a different half.
+    pub fn process(&mut self, l: f32, r: f32) -> (f32, f32) {
+        let n = self.lines.len();
+        let mut outs = [0.0f32; 16];
+
+        // Step 1: read and damp the delay lines
+        for (line, out) in self.lines.iter_mut().zip(outs.iter_mut()) {
+            line.lfo_phase = (line.lfo_phase + line.lfo_rate) % consts::TAU;
+            let offset = line.length + self.mod_depth*(1.0 + line.lfo_phase.sin());
+            let i = offset as usize;
+            let frac = offset - i as f32;
+            let x = math::x_fade(line.buffer.tap(i), frac, line.buffer.tap(i + 1));
+            *out = line.damping.filter(x, self.coef_low, self.coef_high);
+        }
+
+        // Step 2: mix the outputs through the feedback matrix
+        let mut mixed = outs;
+        let mixed = &mut mixed[..n];
+        match self.matrix {
+            MixMatrix::Householder => {
+                let sum = mixed.iter().sum::<f32>() * 2.0 / n as f32;
+                mixed.iter_mut().for_each(|x| *x -= sum);
+            },
+            MixMatrix::Hadamard => hadamard(mixed),
+            MixMatrix::RandomOrthogonal(_) => {
+                for (row, y) in self.random_matrix.chunks(n).zip(mixed.iter_mut()) {
+                    *y = row.iter().zip(outs.iter()).map(|(m, x)| m*x).sum();
+                }
+            },
+        }
+
+        // Step 3: write inputs and feedback, alternating input polarities to
+        // decorrelate the lines
+        for (i, (line, fb)) in self.lines.iter_mut().zip(mixed.iter()).enumerate() {
+            let input = if i % 2 == 0 { l } else { r };
+            let sign = if (i / 2) % 2 == 0 { 1.0 } else { -1.0 };
+            line.buffer.push(input*sign + fb);
+        }
+
+        // Step 4: tap the outputs
+        let scale = (2.0 / n as f32).sqrt();
+        let out_l = outs[..n].iter().step_by(2).sum::<f32>() * scale;
+        let out_r = outs[..n].iter().skip(1).step_by(2).sum::<f32>() * scale;
+        return (out_l, out_r);
+    }
+
+    /// Recompute the band gains of every line from its length and the RT60.
+    fn update_decay(&mut self) {
+        let (low, mid, high) = self.rt60;
+        let sr = self.sr;
+        for line in self.lines.iter_mut() {
+            // gain per pass, so that after rt60 seconds the signal is at -60dB
+            let length = line.length;
+            let gain = |rt60: f32| 10.0f32.powf(-3.0*length/(sr*rt60));
+            line.damping.gain_low = gain(low);
+            line.damping.gain_mid = gain(mid);
+            line.damping.gain_high = gain(high);
+        }
+    }
+}
+
+/// Coefficient of a one-pole lowpass with the given cutoff frequency.
+fn one_pole_coef(f: f32, sr: f32) -> f32 {
+    1.0 - (-consts::TAU*f.clamp(1.0, 0.49*sr)/sr).exp()
+}
+
+/// Normalized in-place fast Walsh-Hadamard transform. The length of `x` must
+/// be a power of two.
+fn hadamard(x: &mut [f32]) {
+    let n = x.len();
+    let mut h = 1;
+    while h < n {
+        for i in (0..n).step_by(2*h) {
+            for j in i..i + h {
+                let (a, b) = (x[j], x[j + h]);
+                x[j] = a + b;
+                x[j + h] = a - b;
+            }
+        }
+        h *= 2;
+    }
+    let scale = 1.0 / (n as f32).sqrt();
+    x.iter_mut().for_each(|v| *v *= scale);
+}
+
+/// Random n-by-n orthogonal matrix in row-major order, from Gram-Schmidt
+/// orthonormalization of random vectors.
+fn random_orthogonal(n: usize, seed: u64) -> Vec<f32> {
+    let mut rng = Rng::new(seed, 44100);
+    let mut m: Vec<f32> = Vec::with_capacity(n*n);
+    while m.len() < n*n {
+        let mut v: Vec<f32> = (0..n).map(|_| rng.randf() - 0.5).collect();
+        for row in m.chunks(n) {
+            let dot: f32 = row.iter().zip(v.iter()).map(|(a, b)| a*b).sum();
+            v.iter_mut().zip(row.iter()).for_each(|(x, r)| *x -= dot*r);
+        }
+        let norm = v.iter().map(|x| x*x).sum::<f32>().sqrt();
+        // retry degenerate vectors, which are almost parallel to the others
+        if norm > 1e-3 {
+            m.extend(v.iter().map(|x| x/norm));
+        }
+    }
+    return m;
+}
+
+/// Smallest prime greater or equal to `n`.
+fn next_prime(n: usize) -> usize {
+    let is_prime = |k: usize| k >= 2 && (2..).take_while(|d| d*d <= k).all(|d| !k.is_multiple_of(d));
+    (n.max(2)..).find(|k| is_prime(*k)).unwrap()
+}
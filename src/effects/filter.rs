@@ -1,108 +1,872 @@
-use std::collections::VecDeque;
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Float;
 
-/// 2-pole state variable filter. Implements lowpass, highpass, notch and
-/// bandpass filters with shared state.
+use crate::effects::delay::{self, InterpMethod, RingBuffer};
+use crate::effects::distortion;
+use crate::effects::filter_design::{self, Band, Cascade, Prototype};
+use crate::utils::math;
+
+/// Frequency response of a linear filter, evaluated from its coefficients
+/// without running audio through it, e.g. to draw EQ curves.
+pub trait FrequencyResponse {
+    /// Complex response of the filter.
+    /// # Parameters
+    /// - freq: frequency in hertz
+    /// - sr: sample rate in hertz
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64>;
+
+    /// Gain of the filter in decibels, see `response`.
+    fn magnitude_db(&self, freq: f64, sr: f64) -> f64 {
+        20.0*self.response(freq, sr).norm().log10()
+    }
+
+    /// Phase shift of the filter in radians, in [-pi, pi], see `response`.
+    fn phase(&self, freq: f64, sr: f64) -> f64 {
+        self.response(freq, sr).arg()
+    }
+
+    /// Group delay of the filter in samples, see `response`.
+    fn group_delay(&self, freq: f64, sr: f64) -> f64 {
+        // central difference of the phase, taken from the ratio of the
+        // responses so that the phase does not need unwrapping
+        let dw = 1e-5;
+        let df = dw*sr/std::f64::consts::TAU;
+        let ratio = self.response(freq + df, sr)/self.response(freq - df, sr);
+        return -ratio.arg()/(2.0*dw);
+    }
+}
+
+/// `z^-1` on the unit circle, at the given frequency.
+#[inline(always)]
+fn z_inv(freq: f64, sr: f64) -> Complex<f64> {
+    Complex::new(0.0, -std::f64::consts::TAU*freq/sr).exp()
+}
+
+/// 2-pole state variable filter. Implements lowpass, highpass, notch,
+/// bandpass, peak, allpass, bell and shelving filters with shared state.
+///
+/// Uses the topology-preserving transform (zero-delay feedback) structure
+/// described by Simper and Zavalishin, so it stays stable and correctly
+/// tuned up to Nyquist, at any resonance and under fast modulation.
+/// See `documentation/svf_filters.md`.
 pub struct Svf {
-    lp_z1:    f32,
-    bp_z1:    f32,
+    ic1eq:    f32,
+    ic2eq:    f32,
+    coefs:    SvfCoefs,
+}
+
+/// Coefficients of an `Svf`, computed from cutoff, resonance and sample
+/// rate. Computing them is the expensive part of filtering, so they can be
+/// cached inside the filter with `Svf::set_params`, or computed once and
+/// shared between many filters with `Svf::process_with`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SvfCoefs {
+    g:  f32,
+    k:  f32,
+    a1: f32,
+    a2: f32,
+    a3: f32,
+}
+
+impl SvfCoefs {
+    /// Compute exact coefficients.
+    /// # Parameters
+    /// - cutoff: cutoff frequency in hertz, kept just below Nyquist
+    /// - res: resonance in [0, 1], where 0 is a Q of 0.5 and 1 self-oscillates
+    /// - sr: sample rate in hertz
+    pub fn new(cutoff: f32, res: f32, sr: f32) -> Self {
+        Self::from_gk(svf_g(cutoff, sr), svf_k(res))
+    }
+
+    /// Frequency response of one of the outputs of a filter running with
+    /// these coefficients.
+    pub fn response(&self, output: SvfOutput) -> SvfResponse {
+        SvfResponse {
+            coefs:  *self,
+            output: output,
+        }
+    }
+
+    /// Compute coefficients with a fast approximation of the cutoff
+    /// prewarping, which avoids calling `tan()`. Meant for audio-rate cutoff
+    /// modulation; the tuning error stays below 0.01 cents up to 0.45*sr.
+    pub fn fast(cutoff: f32, res: f32, sr: f32) -> Self {
+        Self::from_gk(svf_g_fast(cutoff, sr), svf_k(res))
+    }
+
+    #[inline(always)]
+    fn from_gk(g: f32, k: f32) -> Self {
+        let a1 = 1.0 / (1.0 + g*(g + k));
+        let a2 = g*a1;
+        Self {
+            g:  g,
+            k:  k,
+            a1: a1,
+            a2: a2,
+            a3: g*a2,
+        }
+    }
+}
+
+/// All the outputs of an `Svf`, for a single sample.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SvfOutputs {
+    pub lp:      f32,
+    pub hp:      f32,
+    pub notch:   f32,
+    pub bp:      f32,
+    pub peak:    f32,
+    pub allpass: f32,
+}
+
+/// Output of an `Svf`, to evaluate its frequency response.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SvfOutput {
+    Lowpass,
+    Highpass,
+    Notch,
+    Bandpass,
+    Peak,
+    Allpass,
+}
+
+/// Frequency response of one output of an `Svf`, see `SvfCoefs::response`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SvfResponse {
+    coefs:  SvfCoefs,
+    output: SvfOutput,
+}
+
+impl FrequencyResponse for SvfResponse {
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64> {
+        // the filter is the bilinear transform of the analog SVF, with
+        // s = p/q normalized to the cutoff
+        let z = z_inv(freq, sr);
+        let (g, k) = (self.coefs.g as f64, self.coefs.k as f64);
+        let p = 1.0 - z;
+        let q = (1.0 + z)*g;
+        let den = p*p + p*q*k + q*q;
+        let num = match self.output {
+            SvfOutput::Lowpass  => q*q,
+            SvfOutput::Highpass => p*p,
+            SvfOutput::Notch    => p*p + q*q,
+            SvfOutput::Bandpass => p*q,
+            SvfOutput::Peak     => q*q - p*p,
+            SvfOutput::Allpass  => p*p - p*q*k + q*q,
+        };
+        return num/den;
+    }
 }
 
 impl Svf {
     /// Initialize filter state variables.
     pub fn new() -> Self {
         Self {
-            lp_z1:    0.0,
-            bp_z1:    0.0,
+            ic1eq:    0.0,
+            ic2eq:    0.0,
+            coefs:    SvfCoefs::new(1000.0, 0.0, 44100.0),
         }
     }
 
+    /// Set the cached cutoff and resonance used by `process`, see
+    /// `SvfCoefs::new`.
+    pub fn set_params(&mut self, cutoff: f32, res: f32, sr: f32) {
+        self.coefs = SvfCoefs::new(cutoff, res, sr);
+    }
+
+    /// Set the cached cutoff and resonance used by `process`, with the fast
+    /// approximation of `SvfCoefs::fast`. Cheap enough to call every sample.
+    pub fn set_params_fast(&mut self, cutoff: f32, res: f32, sr: f32) {
+        self.coefs = SvfCoefs::fast(cutoff, res, sr);
+    }
+
+    /// Set the cached coefficients used by `process`.
+    pub fn set_coefs(&mut self, coefs: SvfCoefs) {
+        self.coefs = coefs;
+    }
+
+    /// Frequency response of one of the outputs, with the cached
+    /// coefficients.
+    pub fn response(&self, output: SvfOutput) -> SvfResponse {
+        self.coefs.response(output)
+    }
+
+    /// Compute all the outputs of the filter, with the cached coefficients.
+    pub fn process(&mut self, input: f32) -> SvfOutputs {
+        let coefs = self.coefs;
+        return self.process_with(input, &coefs);
+    }
+
+    /// Compute all the outputs of the filter, with the given coefficients.
+    /// This allows sharing coefficients between many filters, e.g. between
+    /// the voices of a synth.
+    pub fn process_with(&mut self, input: f32, coefs: &SvfCoefs) -> SvfOutputs {
+        let (bp, lp) = self.tick(input, coefs);
+        let k = coefs.k;
+        let hp = input - k*bp - lp;
+        return SvfOutputs {
+            lp:      lp,
+            hp:      hp,
+            notch:   lp + hp,
+            bp:      bp,
+            peak:    lp - hp,
+            allpass: input - 2.0*k*bp,
+        };
+    }
+
     /// Compute lowpass, highpass, notch and bandpass filtering of input with
     /// variable resonance and cutoff.
+    ///
+    /// `res` is in [0, 1], where 0 is a Q of 0.5 and 1 self-oscillates.
     pub fn filter(&mut self, input: f32, cutoff: f32, res: f32, sr: f32) -> (f32, f32, f32, f32) {
-        // Pre-process
-        let f = 2.0 * (std::f32::consts::PI*cutoff / sr).sin();
-        let q = (1.0 - res)*2.0;
+        let o = self.filter_all(input, cutoff, res, sr);
+        return (o.lp, o.hp, o.notch, o.bp);
+    }
+
+    /// Compute all the outputs of the filter, with variable resonance and
+    /// cutoff. Recomputes the coefficients on every call, prefer `process`
+    /// when they do not change.
+    pub fn filter_all(&mut self, input: f32, cutoff: f32, res: f32, sr: f32) -> SvfOutputs {
+        return self.process_with(input, &SvfCoefs::new(cutoff, res, sr));
+    }
+
+    /// Bell (peaking EQ) filter, boosting or cutting by `gain` decibels
+    /// around the cutoff. Higher resonance narrows the bell.
+    pub fn bell(&mut self, input: f32, cutoff: f32, res: f32, gain: f32, sr: f32) -> f32 {
+        let a = 10.0f32.powf(gain / 40.0);
+        let k = svf_k(res).max(0.01) / a;
+        let (bp, _) = self.tick(input, &SvfCoefs::from_gk(svf_g(cutoff, sr), k));
+        return input + k*(a*a - 1.0)*bp;
+    }
+
+    /// Low shelving filter, boosting or cutting by `gain` decibels below the
+    /// cutoff.
+    pub fn low_shelf(&mut self, input: f32, cutoff: f32, res: f32, gain: f32, sr: f32) -> f32 {
+        let a = 10.0f32.powf(gain / 40.0);
+        let k = svf_k(res).max(0.01);
+        let (bp, lp) = self.tick(input, &SvfCoefs::from_gk(svf_g(cutoff, sr) / a.sqrt(), k));
+        return input + k*(a - 1.0)*bp + (a*a - 1.0)*lp;
+    }
+
+    /// High shelving filter, boosting or cutting by `gain` decibels above
+    /// the cutoff.
+    pub fn high_shelf(&mut self, input: f32, cutoff: f32, res: f32, gain: f32, sr: f32) -> f32 {
+        let a = 10.0f32.powf(gain / 40.0);
+        let k = svf_k(res).max(0.01);
+        let (bp, lp) = self.tick(input, &SvfCoefs::from_gk(svf_g(cutoff, sr) * a.sqrt(), k));
+        return a*a*input + k*(1.0 - a)*a*bp + (1.0 - a*a)*lp;
+    }
+
+    /// Advance the filter by one sample.
+    /// # Returns
+    /// - bandpass and lowpass outputs, from which all others are derived
+    #[inline(always)]
+    fn tick(&mut self, v0: f32, c: &SvfCoefs) -> (f32, f32) {
+        let v3 = v0 - self.ic2eq;
+        let v1 = c.a1*self.ic1eq + c.a2*v3;
+        let v2 = self.ic2eq + c.a2*self.ic1eq + c.a3*v3;
+
+        // Update state:
+        self.ic1eq = 2.0*v1 - self.ic1eq;
+        self.ic2eq = 2.0*v2 - self.ic2eq;
+        return (v1, v2);
+    }
+}
+
+/// Frequency coefficient of the `Svf`, prewarped with the bilinear
+/// transform. The cutoff is kept just below Nyquist.
+#[inline(always)]
+fn svf_g(cutoff: f32, sr: f32) -> f32 {
+    (std::f32::consts::PI * cutoff.clamp(0.0, 0.4999*sr) / sr).tan()
+}
+
+/// Approximation of `svf_g`, see `SvfCoefs::fast`.
+#[inline(always)]
+fn svf_g_fast(cutoff: f32, sr: f32) -> f32 {
+    math::fast_tan(std::f32::consts::PI * cutoff.clamp(0.0, 0.4999*sr) / sr)
+}
+
+/// Damping coefficient of the `Svf`, i.e. 1/Q, from the resonance.
+#[inline(always)]
+fn svf_k(res: f32) -> f32 {
+    (1.0 - res.clamp(0.0, 1.0))*2.0
+}
+
+/// Saturation applied inside the loop of a `NonlinearSvf`. All shapers
+/// have unity gain for small signals and saturate at 1.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SvfShaper {
+    /// Approximation of tanh, see `math::fast_tanh`.
+    Tanh,
+    /// `distortion::var_clip` with the given hardness in [0, 1], from soft
+    /// to hard clipping.
+    VarClip(f32),
+}
+
+impl SvfShaper {
+    #[inline(always)]
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            SvfShaper::Tanh => math::fast_tanh(x),
+            SvfShaper::VarClip(hardness) => {
+                // var_clip has a slope of 1/0.1^k at 0, scale it back to 1
+                let k = 1.0 - hardness.clamp(0.0, 0.9999);
+                distortion::var_clip(x*0.1f32.powf(k), hardness)
+            }
+        }
+    }
+}
+
+/// State variable filter with saturating integrators, like the OTA-based
+/// filters of analog synths. Uses the same structure as `Svf`, but the
+/// outputs of both integrators pass through a shaper before updating the
+/// state, so the filter compresses loud signals. The resonance can go past
+/// 1, where the damping becomes negative and the filter self-oscillates with
+/// an amplitude bounded by the shaper.
+///
+/// Small signals are filtered exactly like with `Svf`.
+pub struct NonlinearSvf {
+    ic1eq:    f32,
+    ic2eq:    f32,
+    coefs:    SvfCoefs,
+    shaper:   SvfShaper,
+    drive:    f32,
+}
+
+impl NonlinearSvf {
+    /// Initialize filter state variables.
+    pub fn new(shaper: SvfShaper) -> Self {
+        Self {
+            ic1eq:    0.0,
+            ic2eq:    0.0,
+            coefs:    SvfCoefs::new(1000.0, 0.0, 44100.0),
+            shaper:   shaper,
+            drive:    1.0,
+        }
+    }
 
-        // Filtering
-        let lp    = self.bp_z1*f + self.lp_z1;
-        let hp    = input - lp - q*self.bp_z1;
-        let notch = hp + lp;
-        let bp    = hp*f + self.bp_z1;
+    /// Set the cached cutoff and resonance.
+    /// # Parameters
+    /// - cutoff: cutoff frequency in hertz, kept just below Nyquist
+    /// - res: resonance in [0, 1.2], where 0 is a Q of 0.5 and the filter
+    ///   self-oscillates above 1
+    /// - sr: sample rate in hertz
+    pub fn set_params(&mut self, cutoff: f32, res: f32, sr: f32) {
+        self.coefs = SvfCoefs::from_gk(svf_g(cutoff, sr), nonlinear_svf_k(res));
+    }
+
+    /// Set the cached cutoff and resonance, with the fast approximation of
+    /// `SvfCoefs::fast`. Cheap enough to call every sample.
+    pub fn set_params_fast(&mut self, cutoff: f32, res: f32, sr: f32) {
+        self.coefs = SvfCoefs::from_gk(svf_g_fast(cutoff, sr), nonlinear_svf_k(res));
+    }
+
+    /// Set the cached coefficients.
+    pub fn set_coefs(&mut self, coefs: SvfCoefs) {
+        self.coefs = coefs;
+    }
+
+    /// Frequency response of one of the outputs for small signals, where
+    /// the shapers are linear.
+    pub fn response(&self, output: SvfOutput) -> SvfResponse {
+        self.coefs.response(output)
+    }
+
+    /// Set the shaper of the integrators.
+    pub fn set_shaper(&mut self, shaper: SvfShaper) {
+        self.shaper = shaper;
+    }
+
+    /// Set the gain into the integrators. The outputs are scaled back by the
+    /// same amount, so that higher drive only adds saturation.
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive.max(1e-3);
+    }
+
+    /// Compute all the outputs of the filter.
+    pub fn process(&mut self, input: f32) -> SvfOutputs {
+        let c = self.coefs;
+        let v0 = input*self.drive;
+
+        // solve the linear filter, then saturate the integrator outputs
+        let v3 = v0 - self.ic2eq;
+        let v1 = self.shaper.apply(c.a1*self.ic1eq + c.a2*v3);
+        let v2 = self.shaper.apply(self.ic2eq + c.g*v1);
 
         // Update state:
-        self.lp_z1    = lp;
-        self.bp_z1    = bp;
+        self.ic1eq = 2.0*v1 - self.ic1eq;
+        self.ic2eq = 2.0*v2 - self.ic2eq;
 
-        // return 
-        return (lp, hp, notch, bp);
+        let (bp, lp) = (v1/self.drive, v2/self.drive);
+        let hp = input - c.k*bp - lp;
+        return SvfOutputs {
+            lp:      lp,
+            hp:      hp,
+            notch:   lp + hp,
+            bp:      bp,
+            peak:    lp - hp,
+            allpass: input - 2.0*c.k*bp,
+        };
     }
 }
 
-/// DC offset blocking filter.
+/// Damping coefficient of the `NonlinearSvf`, which goes negative above a
+/// resonance of 1.
+#[inline(always)]
+fn nonlinear_svf_k(res: f32) -> f32 {
+    (1.0 - res.clamp(0.0, 1.2))*2.0
+}
+
+/// Response of a `Biquad`, from the Audio EQ Cookbook by Robert
+/// Bristow-Johnson. Gains are in decibels.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BiquadType {
+    Lowpass,
+    Highpass,
+    /// Bandpass with 0dB gain at the center frequency.
+    Bandpass,
+    Notch,
+    Allpass,
+    Peaking(f64),
+    LowShelf(f64),
+    HighShelf(f64),
+}
+
+/// Structure used to run a `Biquad`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BiquadForm {
+    /// Direct Form I. Robust to coefficient changes, uses 4 state variables.
+    DirectForm1,
+    /// Transposed Direct Form II. Better numerical behaviour in floating
+    /// point, uses 2 state variables.
+    TransposedDirectForm2,
+}
+
+/// Coefficients of a `Biquad`, normalized so that `a0 = 1`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BiquadCoefs<T> {
+    pub b0: T,
+    pub b1: T,
+    pub b2: T,
+    pub a1: T,
+    pub a2: T,
+}
+
+impl<T: Float> BiquadCoefs<T> {
+    /// Compute the coefficients of a cookbook filter.
+    /// # Parameters
+    /// - kind: filter response
+    /// - freq: cutoff or center frequency in hertz, kept below Nyquist
+    /// - q: quality factor, also sets the slope of the shelves
+    /// - sr: sample rate in hertz
+    pub fn new(kind: BiquadType, freq: T, q: T, sr: T) -> Self {
+        let c = |x: f64| T::from(x).unwrap();
+        let w0 = c(std::f64::consts::TAU) * freq.max(T::zero()).min(c(0.4999)*sr) / sr;
+        let (sin_w0, cos_w0) = w0.sin_cos();
+        let alpha = sin_w0 / (c(2.0)*q.max(c(1e-3)));
+        let one = T::one();
+        let two = c(2.0);
+        let gain = |db: f64| c(10.0).powf(c(db / 40.0));
+
+        let (b0, b1, b2, a0, a1, a2) = match kind {
+            BiquadType::Lowpass => {
+                let b1 = one - cos_w0;
+                (b1/two, b1, b1/two, one + alpha, -two*cos_w0, one - alpha)
+            },
+            BiquadType::Highpass => {
+                let b1 = -(one + cos_w0);
+                (-b1/two, b1, -b1/two, one + alpha, -two*cos_w0, one - alpha)
+            },
+            BiquadType::Bandpass =>
+                (alpha, T::zero(), -alpha, one + alpha, -two*cos_w0, one - alpha),
+            BiquadType::Notch =>
+                (one, -two*cos_w0, one, one + alpha, -two*cos_w0, one - alpha),
+            BiquadType::Allpass =>
+                (one - alpha, -two*cos_w0, one + alpha, one + alpha, -two*cos_w0, one - alpha),
+            BiquadType::Peaking(db) => {
+                let a = gain(db);
+                (one + alpha*a, -two*cos_w0, one - alpha*a, one + alpha/a, -two*cos_w0, one - alpha/a)
+            },
+            BiquadType::LowShelf(db) => {
+                let a = gain(db);
+                let k = two*a.sqrt()*alpha;
+                (
+                    a*((a + one) - (a - one)*cos_w0 + k),
+                    two*a*((a - one) - (a + one)*cos_w0),
+                    a*((a + one) - (a - one)*cos_w0 - k),
+                    (a + one) + (a - one)*cos_w0 + k,
+                    -two*((a - one) + (a + one)*cos_w0),
+                    (a + one) + (a - one)*cos_w0 - k,
+                )
+            },
+            BiquadType::HighShelf(db) => {
+                let a = gain(db);
+                let k = two*a.sqrt()*alpha;
+                (
+                    a*((a + one) + (a - one)*cos_w0 + k),
+                    -two*a*((a - one) + (a + one)*cos_w0),
+                    a*((a + one) + (a - one)*cos_w0 - k),
+                    (a + one) - (a - one)*cos_w0 + k,
+                    two*((a - one) - (a + one)*cos_w0),
+                    (a + one) - (a - one)*cos_w0 - k,
+                )
+            },
+        };
+        Self {
+            b0: b0/a0,
+            b1: b1/a0,
+            b2: b2/a0,
+            a1: a1/a0,
+            a2: a2/a0,
+        }
+    }
+
+    /// Coefficients of a filter that passes the input unchanged.
+    pub fn identity() -> Self {
+        Self {
+            b0: T::one(),
+            b1: T::zero(),
+            b2: T::zero(),
+            a1: T::zero(),
+            a2: T::zero(),
+        }
+    }
+}
+
+impl<T: Float> FrequencyResponse for BiquadCoefs<T> {
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64> {
+        let c = |x: T| x.to_f64().unwrap();
+        let z = z_inv(freq, sr);
+        return (z*(z*c(self.b2) + c(self.b1)) + c(self.b0))
+            / (z*(z*c(self.a2) + c(self.a1)) + 1.0);
+    }
+}
+
+/// 2-pole, 2-zero filter, in single (`f32`) or double (`f64`) precision.
+///
+/// Coefficient changes can be ramped to avoid zipper noise, see
+/// `set_smoothing`.
+pub struct Biquad<T> {
+    coefs: BiquadCoefs<T>,
+    target: BiquadCoefs<T>,
+    step: BiquadCoefs<T>,
+    ramp_len: usize,
+    ramp_left: usize,
+    form: BiquadForm,
+    x_z1: T,
+    x_z2: T,
+    y_z1: T,
+    y_z2: T,
+}
+
+impl<T: Float> Biquad<T> {
+    /// Initialize filter state variables, with coefficients that pass the
+    /// input unchanged.
+    pub fn new(form: BiquadForm) -> Self {
+        Self {
+            coefs: BiquadCoefs::identity(),
+            target: BiquadCoefs::identity(),
+            step: BiquadCoefs::identity(),
+            ramp_len: 0,
+            ramp_left: 0,
+            form: form,
+            x_z1: T::zero(),
+            x_z2: T::zero(),
+            y_z1: T::zero(),
+            y_z2: T::zero(),
+        }
+    }
+
+    /// Set the response of the filter, see `BiquadCoefs::new`.
+    pub fn set_params(&mut self, kind: BiquadType, freq: T, q: T, sr: T) {
+        self.set_coefs(BiquadCoefs::new(kind, freq, q, sr));
+    }
+
+    /// Set the coefficients of the filter. If smoothing is enabled, the
+    /// filter ramps linearly towards them.
+    pub fn set_coefs(&mut self, coefs: BiquadCoefs<T>) {
+        self.target = coefs;
+        if self.ramp_len == 0 {
+            self.coefs = coefs;
+            self.ramp_left = 0;
+        } else {
+            let n = T::from(self.ramp_len).unwrap();
+            let (c, t) = (&self.coefs, &self.target);
+            self.step = BiquadCoefs {
+                b0: (t.b0 - c.b0)/n,
+                b1: (t.b1 - c.b1)/n,
+                b2: (t.b2 - c.b2)/n,
+                a1: (t.a1 - c.a1)/n,
+                a2: (t.a2 - c.a2)/n,
+            };
+            self.ramp_left = self.ramp_len;
+        }
+    }
+
+    /// Set the duration of the coefficient ramps, in milliseconds. A time of
+    /// 0 disables smoothing. Keep it short (a few milliseconds) when jumping
+    /// between very different responses, as intermediate coefficients are not
+    /// guaranteed to be stable.
+    pub fn set_smoothing(&mut self, time: T, sr: T) {
+        let samples = time * sr / T::from(1000.0).unwrap();
+        self.ramp_len = samples.max(T::zero()).to_usize().unwrap_or(0);
+    }
+
+    /// Filter a single sample.
+    pub fn filter(&mut self, x: T) -> T {
+        if self.ramp_left > 0 {
+            self.ramp_left -= 1;
+            if self.ramp_left == 0 {
+                self.coefs = self.target;
+            } else {
+                let (c, d) = (&mut self.coefs, &self.step);
+                c.b0 = c.b0 + d.b0;
+                c.b1 = c.b1 + d.b1;
+                c.b2 = c.b2 + d.b2;
+                c.a1 = c.a1 + d.a1;
+                c.a2 = c.a2 + d.a2;
+            }
+        }
+        let c = &self.coefs;
+        match self.form {
+            BiquadForm::DirectForm1 => {
+                let y = c.b0*x + c.b1*self.x_z1 + c.b2*self.x_z2
+                    - c.a1*self.y_z1 - c.a2*self.y_z2;
+                self.x_z2 = self.x_z1;
+                self.x_z1 = x;
+                self.y_z2 = self.y_z1;
+                self.y_z1 = y;
+                return y;
+            },
+            BiquadForm::TransposedDirectForm2 => {
+                // y_z1 and y_z2 hold the two accumulators
+                let y = c.b0*x + self.y_z1;
+                self.y_z1 = c.b1*x - c.a1*y + self.y_z2;
+                self.y_z2 = c.b2*x - c.a2*y;
+                return y;
+            },
+        }
+    }
+
+    /// Current coefficients, which may still be ramping towards the target.
+    pub fn coefs(&self) -> BiquadCoefs<T> {
+        self.coefs
+    }
+
+    /// Reset the state of the filter, and jump to the target coefficients.
+    pub fn reset(&mut self) {
+        self.coefs = self.target;
+        self.ramp_left = 0;
+        self.x_z1 = T::zero();
+        self.x_z2 = T::zero();
+        self.y_z1 = T::zero();
+        self.y_z2 = T::zero();
+    }
+}
+
+/// The response of the target coefficients, i.e. once the smoothing is over.
+impl<T: Float> FrequencyResponse for Biquad<T> {
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64> {
+        self.target.response(freq, sr)
+    }
+}
+
+// Cutoffs of the `BlockDC` presets, matching poles of 0.995, 0.9 and 0.5 at
+// 44.1kHz.
+const DC_WEAK_CUTOFF: f32 = 35.18;
+const DC_MEDIUM_CUTOFF: f32 = 739.5;
+const DC_STRONG_CUTOFF: f32 = 4865.0;
+
+/// DC offset blocking filter. The first-order filter is the classic
+/// one-zero, one-pole blocker; higher orders use a Butterworth highpass for
+/// a steeper removal of sub-sonic content.
 pub struct BlockDC {
     x_z1: f32,
     y_z1: f32,
+    pole: f32,
+    order: usize,
+    sections: Cascade<f32>,
 }
 
 impl BlockDC {
-    /// Initialize filter state variables.
+    /// Create a first-order DC blocker with the weak preset at 44.1kHz, the
+    /// pole of `filter_weak`.
     pub fn new() -> Self {
-        Self {
+        return Self {
             x_z1: 0.0,
             y_z1: 0.0,
-        }
+            pole: 0.995,
+            order: 1,
+            sections: Cascade::new(&[]),
+        };
+    }
+
+    /// Create a first-order DC blocker.
+    /// # Parameters
+    /// - cutoff: cutoff frequency in hertz
+    /// - sr: sample rate in hertz
+    pub fn from_cutoff(cutoff: f32, sr: f32) -> Self {
+        return Self::with_order(cutoff, 1, sr);
+    }
+
+    /// Create a DC blocker of any order, with a slope of 6dB/octave per
+    /// order below the cutoff.
+    /// # Parameters
+    /// - cutoff: cutoff frequency in hertz
+    /// - order: order of the filter, at least 1
+    /// - sr: sample rate in hertz
+    pub fn with_order(cutoff: f32, order: usize, sr: f32) -> Self {
+        let mut ret = Self {
+            x_z1: 0.0,
+            y_z1: 0.0,
+            pole: 0.0,
+            order: order.max(1),
+            sections: Cascade::new(&[]),
+        };
+        ret.set_cutoff(cutoff, sr);
+        return ret;
     }
 
     /// Weak DC blocker. Use for blocking constant DC in input.
-    pub fn filter_weak(&mut self, input: f32) -> f32 {
-        self.y_z1 = input - self.x_z1 + 0.995*self.y_z1;
-        self.x_z1 = input;
-        return self.y_z1;
+    pub fn weak(sr: f32) -> Self {
+        return Self::from_cutoff(DC_WEAK_CUTOFF, sr);
     }
 
     /// Medium DC blocker. Use for blocking sub-sonic sound in input.
-    pub fn filter_medium(&mut self, input: f32) -> f32 {
-        self.y_z1 = input - self.x_z1 + 0.9*self.y_z1;
-        self.x_z1 = input;
-        return self.y_z1;
+    pub fn medium(sr: f32) -> Self {
+        return Self::from_cutoff(DC_MEDIUM_CUTOFF, sr);
     }
 
     /// Strong DC blocker. Use for blocking DC in feedback loops, i.e. for stabilizing
     /// an unstable feedback loop. For this application you might want to combine
     /// it with a highpass filter at approximately 18kHz.
-    /// 
+    ///
     /// Note also that this filter will remove some of the sub bass, so use only
     /// when strictly necessary.
+    pub fn strong(sr: f32) -> Self {
+        return Self::from_cutoff(DC_STRONG_CUTOFF, sr);
+    }
+
+    /// Set the cutoff frequency in hertz. The state of the filter is kept.
+    pub fn set_cutoff(&mut self, cutoff: f32, sr: f32) {
+        let cutoff = cutoff.clamp(0.0, 0.45*sr);
+        if self.order == 1 {
+            self.pole = (-std::f32::consts::TAU*cutoff/sr).exp();
+        } else {
+            self.sections.set_coefs(&filter_design::design(
+                Prototype::Butterworth, Band::Highpass, self.order, cutoff as f64, sr as f64
+            ));
+        }
+    }
+
+    /// Filter a sample.
+    pub fn filter(&mut self, input: f32) -> f32 {
+        if self.order > 1 {
+            return self.sections.filter(input);
+        }
+        return self.filter_first_order(self.pole, input);
+    }
+
+    /// Weak DC blocker, with the fixed pole of earlier versions. The order
+    /// and cutoff of the filter are ignored.
+    #[deprecated(note = "use `BlockDC::weak(sr)` and `filter`")]
+    pub fn filter_weak(&mut self, input: f32) -> f32 {
+        return self.filter_first_order(0.995, input);
+    }
+
+    /// Medium DC blocker, with the fixed pole of earlier versions. The order
+    /// and cutoff of the filter are ignored.
+    #[deprecated(note = "use `BlockDC::medium(sr)` and `filter`")]
+    pub fn filter_medium(&mut self, input: f32) -> f32 {
+        return self.filter_first_order(0.9, input);
+    }
+
+    /// Strong DC blocker, with the fixed pole of earlier versions. The order
+    /// and cutoff of the filter are ignored.
+    #[deprecated(note = "use `BlockDC::strong(sr)` and `filter`")]
     pub fn filter_strong(&mut self, input: f32) -> f32 {
-        self.y_z1 = input - self.x_z1 + 0.5*self.y_z1;
+        return self.filter_first_order(0.5, input);
+    }
+
+    fn filter_first_order(&mut self, pole: f32, input: f32) -> f32 {
+        self.y_z1 = input - self.x_z1 + pole*self.y_z1;
         self.x_z1 = input;
         return self.y_z1;
     }
 }
 
-/// Allpass filter with negative feedback and a set cutoff frequency.
+impl Default for BlockDC {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl FrequencyResponse for BlockDC {
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64> {
+        if self.order > 1 {
+            return self.sections.response(freq, sr);
+        }
+        let z = z_inv(freq, sr);
+        return (1.0 - z)/(1.0 - z*self.pole as f64);
+    }
+}
+
+/// Schroeder allpass filter with negative feedback and a set cutoff
+/// frequency, i.e. a delay of `1/f`. The delay is fractional, read with
+/// Hermite interpolation, so the frequency is exact and can be modulated.
 pub struct AllPass {
-    line: VecDeque<f32>,
+    line: RingBuffer,
+    delay: f32,
+    max_delay: f32,
     fb: f32,
 }
 
 impl AllPass {
-    /// Create a new filter with a set cutoff frequency. Note that the cutoff
-    /// frequency will not be achieved exactly, as the internal delay is always
-    /// an integer amount of samples long to avoid interpolation artifacts.
+    /// Create a new filter with a set cutoff frequency. The frequency can
+    /// then only be raised, use `with_max_delay` to leave room for modulation.
+    /// # Parameters
+    /// - f: cutoff frequency in hertz
+    /// - fb: feedback in (-1, 1)
+    /// - sr: sample rate in hertz
     pub fn new(f: f32, fb: f32, sr: u32) -> Self {
-        let num = (1.0/f * sr as f32) as usize + 1;
-        Self {
-            line: VecDeque::from(vec![0.0; num]),
+        let delay = sr as f32 / f;
+        return Self::with_max_delay(delay, delay, fb);
+    }
+
+    /// Create a new filter from its delay.
+    /// # Parameters
+    /// - delay: delay in samples, at least 1
+    /// - max_delay: longest delay that can be set later, in samples
+    /// - fb: feedback in (-1, 1)
+    pub fn with_max_delay(delay: f32, max_delay: f32, fb: f32) -> Self {
+        let max_delay = max_delay.max(delay).max(1.0);
+        let mut ret = Self {
+            // room for the neighbours of the Hermite interpolation
+            line: RingBuffer::new(max_delay as usize + 3),
+            delay: 1.0,
+            max_delay: max_delay,
             fb: fb,
-        }
+        };
+        ret.set_delay(delay);
+        return ret;
+    }
+
+    /// Set the cutoff frequency in hertz.
+    pub fn set_freq(&mut self, f: f32, sr: u32) {
+        self.set_delay(sr as f32 / f);
+    }
+
+    /// Set the delay in samples, between 1 and the maximum delay. Can be
+    /// modulated at audio rate.
+    pub fn set_delay(&mut self, delay: f32) {
+        self.delay = delay.clamp(1.0, self.max_delay);
     }
 
     /// Filter a single sample with negative feedback.
     pub fn filter(&mut self, input: f32) -> f32 {
-        let y_z1 = self.line.pop_front().unwrap();
+        let y_z1 = delay::read(&self.line, &InterpMethod::Hermite, &[], self.delay - 1.0, &mut 0.0);
         let x = input - y_z1*self.fb;
-        self.line.push_back(x);
+        self.line.push(x);
         return y_z1 + x*self.fb;
     }
 
@@ -112,59 +876,349 @@ impl AllPass {
     }
 }
 
+/// The response of the ideal fractional delay, without the small
+/// high-frequency loss of the interpolation.
+impl FrequencyResponse for AllPass {
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64> {
+        let z_d = Complex::new(0.0, -std::f64::consts::TAU*freq/sr*self.delay as f64).exp();
+        let fb = self.fb as f64;
+        return (z_d + fb)/(z_d*fb + 1.0);
+    }
+}
+
+/// First-order allpass filter, with a phase shift of -90 degrees at its
+/// break frequency, going from 0 at DC to -180 degrees at Nyquist.
+pub struct FirstOrderAllPass {
+    a: f32,
+    x_z1: f32,
+    y_z1: f32,
+}
+
+impl FirstOrderAllPass {
+    /// Create a new filter.
+    /// # Parameters
+    /// - freq: break frequency in hertz
+    /// - sr: sample rate in hertz
+    pub fn new(freq: f32, sr: f32) -> Self {
+        let mut ret = Self {
+            a: 0.0,
+            x_z1: 0.0,
+            y_z1: 0.0,
+        };
+        ret.set_freq(freq, sr);
+        return ret;
+    }
+
+    /// Create a new filter from its coefficient `a`, with the transfer
+    /// function `(a + z^-1)/(1 + a*z^-1)`. Stable for `|a| < 1`.
+    pub fn from_coef(a: f32) -> Self {
+        Self {
+            a: a,
+            x_z1: 0.0,
+            y_z1: 0.0,
+        }
+    }
+
+    /// Set the break frequency in hertz.
+    pub fn set_freq(&mut self, freq: f32, sr: f32) {
+        self.set_g(svf_g(freq, sr));
+    }
+
+    /// Set the break frequency in hertz, with the fast approximation of
+    /// `SvfCoefs::fast`. Cheap enough to call every sample.
+    pub fn set_freq_fast(&mut self, freq: f32, sr: f32) {
+        self.set_g(svf_g_fast(freq, sr));
+    }
+
+    /// Filter a single sample.
+    pub fn filter(&mut self, input: f32) -> f32 {
+        let y = self.a*(input - self.y_z1) + self.x_z1;
+        self.x_z1 = input;
+        self.y_z1 = y;
+        return y;
+    }
+
+    /// Reset the state of the filter.
+    pub fn reset(&mut self) {
+        self.x_z1 = 0.0;
+        self.y_z1 = 0.0;
+    }
+
+    fn set_g(&mut self, g: f32) {
+        self.a = (g - 1.0)/(g + 1.0);
+    }
+}
+
+impl FrequencyResponse for FirstOrderAllPass {
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64> {
+        let z = z_inv(freq, sr);
+        let a = self.a as f64;
+        return (z + a)/(z*a + 1.0);
+    }
+}
+
+/// Cascade of first-order allpasses, e.g. for phasers (mixed with the dry
+/// signal, each pair of stages adds a notch) or dispersion effects.
+pub struct AllPassChain {
+    stages: Vec<FirstOrderAllPass>,
+}
+
+impl AllPassChain {
+    /// Create a new cascade.
+    /// # Parameters
+    /// - stages: number of first-order sections
+    /// - freq: break frequency of all sections in hertz
+    /// - sr: sample rate in hertz
+    pub fn new(stages: usize, freq: f32, sr: f32) -> Self {
+        Self {
+            stages: (0..stages).map(|_| FirstOrderAllPass::new(freq, sr)).collect(),
+        }
+    }
+
+    /// Set the break frequency of all the sections, in hertz. Uses the fast
+    /// approximation of `SvfCoefs::fast`, so it can be modulated at audio
+    /// rate.
+    pub fn set_freq(&mut self, freq: f32, sr: f32) {
+        self.stages.iter_mut().for_each(|s| s.set_freq_fast(freq, sr));
+    }
+
+    /// Set the break frequency of each section in hertz, like `set_freq`.
+    /// Extra frequencies are ignored.
+    pub fn set_freqs(&mut self, freqs: &[f32], sr: f32) {
+        for (s, f) in self.stages.iter_mut().zip(freqs) {
+            s.set_freq_fast(*f, sr);
+        }
+    }
+
+    /// Filter a single sample.
+    pub fn filter(&mut self, input: f32) -> f32 {
+        self.stages.iter_mut().fold(input, |acc, s| s.filter(acc))
+    }
+}
+
+impl FrequencyResponse for AllPassChain {
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64> {
+        self.stages.iter().map(|s| s.response(freq, sr)).product()
+    }
+}
+
+/// Comb filter with a one-pole lowpass in the feedback path, as used in
+/// Schroeder and Freeverb reverbs. Higher damping makes the high frequencies
+/// decay faster than the low ones.
+pub struct LpComb {
+    line: RingBuffer,
+    len: usize,
+    fb: f32,
+    damp: f32,
+    lp_z1: f32,
+}
+
+impl LpComb {
+    /// Create a new filter with its first resonance at the given frequency.
+    /// The delay is an integer amount of samples long, rounded down from
+    /// `sr/f`.
+    pub fn new(f: f32, fb: f32, damp: f32, sr: u32) -> Self {
+        Self::with_len((sr as f32 / f) as usize, fb, damp)
+    }
+
+    /// Create a new filter with a delay of exactly `len` samples.
+    pub fn with_len(len: usize, fb: f32, damp: f32) -> Self {
+        let len = len.max(1);
+        Self {
+            line: RingBuffer::new(len),
+            len: len,
+            fb: fb,
+            damp: damp.clamp(0.0, 1.0),
+            lp_z1: 0.0,
+        }
+    }
+
+    /// Filter a single sample.
+    pub fn filter(&mut self, input: f32) -> f32 {
+        let y = self.line.tap(self.len - 1);
+        self.lp_z1 = y*(1.0 - self.damp) + self.lp_z1*self.damp;
+        self.line.push(input + self.lp_z1*self.fb);
+        return y;
+    }
+
+    /// Change the feedback of the filter
+    pub fn set_fb(&mut self, fb: f32) {
+        self.fb = fb;
+    }
+
+    /// Change the damping of the filter, in [0, 1]
+    pub fn set_damp(&mut self, damp: f32) {
+        self.damp = damp.clamp(0.0, 1.0);
+    }
+}
+
+impl FrequencyResponse for LpComb {
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64> {
+        let z = z_inv(freq, sr);
+        let z_len = z.powu(self.len as u32);
+        let damp = self.damp as f64;
+        let lp = (1.0 - damp)/(1.0 - z*damp);
+        return z_len/(1.0 - lp*z_len*self.fb as f64);
+    }
+}
+
+/// Discretization used by `Integrator` and `Diff`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IntegrationMethod {
+    /// Running sum, `y[n] = y[n-1] + x[n]*delta`, and its inverse, the
+    /// first difference. Cheap, but the integral drifts without bound on
+    /// any DC input.
+    ForwardEuler,
+    /// Trapezoidal rule, i.e. the bilinear transform of `1/s` and `s`.
+    /// Exact in phase, but the differentiator rings at Nyquist on sharp
+    /// transients.
+    Trapezoidal,
+    /// Trapezoidal rule with a leak of the given time constant, in the unit
+    /// of `delta` (samples for `filter`). The integrator becomes
+    /// `1/(s + 1/t)`, which forgets DC instead of drifting, and the
+    /// differentiator becomes `s/(1 + s*t)`, which stops rising above
+    /// `1/(2*pi*t)`.
+    Leaky(f32),
+}
+
 /// 1-pole integrator
 pub struct Integrator {
     prev: f32,
+    x_z1: f32,
+    method: IntegrationMethod,
 }
 
 impl Integrator {
+    /// Create a forward Euler integrator.
     pub fn new() -> Self {
+        return Self::with_method(IntegrationMethod::ForwardEuler);
+    }
+
+    /// Create an integrator with the given discretization.
+    pub fn with_method(method: IntegrationMethod) -> Self {
         Self {
             prev: 0.0,
+            x_z1: 0.0,
+            method: method,
         }
     }
 
+    /// Change the discretization, keeping the state.
+    pub fn set_method(&mut self, method: IntegrationMethod) {
+        self.method = method;
+    }
+
     /// Find the numeric integral of x for the time interval delta. This is
     /// inlined for use within algorithms.
     #[inline(always)]
     pub fn integrate(&mut self, x: f32, delta: f32) -> f32 {
-        self.prev += x * delta;
+        self.prev = match self.method {
+            IntegrationMethod::ForwardEuler => self.prev + x*delta,
+            IntegrationMethod::Trapezoidal => self.prev + 0.5*delta*(x + self.x_z1),
+            IntegrationMethod::Leaky(time) => {
+                let c = 0.5*delta/time.max(1e-9);
+                ((1.0 - c)*self.prev + 0.5*delta*(x + self.x_z1))/(1.0 + c)
+            }
+        };
+        self.x_z1 = x;
         return self.prev;
     }
 
     /// Find the numeric integral of x, for delta = 1
     pub fn filter(&mut self, x: f32) -> f32 {
-        self.prev += x;
-        return self.prev;
+        return self.integrate(x, 1.0);
+    }
+
+    /// Reset the integral to 0.
+    pub fn reset(&mut self) {
+        self.prev = 0.0;
+        self.x_z1 = 0.0;
     }
 }
 
+/// The response of `filter`, i.e. for delta = 1.
+impl FrequencyResponse for Integrator {
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64> {
+        let z = z_inv(freq, sr);
+        match self.method {
+            IntegrationMethod::ForwardEuler => 1.0/(1.0 - z),
+            IntegrationMethod::Trapezoidal => (1.0 + z)*0.5/(1.0 - z),
+            IntegrationMethod::Leaky(time) => {
+                let c = 0.5/(time as f64).max(1e-9);
+                (1.0 + z)*0.5/((1.0 + c) - z*(1.0 - c))
+            }
+        }
+    }
+}
 
 /// 1-pole differentiator
-pub struct Diff{
+pub struct Diff {
     prev: f32,
+    y_z1: f32,
+    method: IntegrationMethod,
 }
 
-impl Diff{
+impl Diff {
+    /// Create a forward Euler differentiator, i.e. a first difference.
     pub fn new() -> Self {
+        return Self::with_method(IntegrationMethod::ForwardEuler);
+    }
+
+    /// Create a differentiator with the given discretization.
+    pub fn with_method(method: IntegrationMethod) -> Self {
         Self {
             prev: 0.0,
+            y_z1: 0.0,
+            method: method,
         }
     }
 
+    /// Change the discretization, keeping the state.
+    pub fn set_method(&mut self, method: IntegrationMethod) {
+        self.method = method;
+    }
+
     /// Find the numeric derivative of x for the given time interval. This is
     /// inlined for use within algorithms.
     #[inline(always)]
     pub fn diff(&mut self, x: f32, delta: f32) -> f32 {
         let dx = x - self.prev;
+        let y = match self.method {
+            IntegrationMethod::ForwardEuler => dx/delta,
+            IntegrationMethod::Trapezoidal => 2.0*dx/delta - self.y_z1,
+            IntegrationMethod::Leaky(time) => {
+                let c = 2.0*time/delta;
+                (2.0*dx/delta - (1.0 - c)*self.y_z1)/(1.0 + c)
+            }
+        };
         self.prev = x;
-        return dx / delta ;
+        self.y_z1 = y;
+        return y;
     }
 
     /// Find the numeric derivative of x, for delta = 1
     pub fn filter(&mut self, x: f32) -> f32 {
-        let ret = x - self.prev;
-        self.prev = x;
-        return x;
+        return self.diff(x, 1.0);
     }
-}
\ No newline at end of file
+
+    /// Reset the state to 0.
+    pub fn reset(&mut self) {
+        self.prev = 0.0;
+        self.y_z1 = 0.0;
+    }
+}
+
+/// The response of `filter`, i.e. for delta = 1.
+impl FrequencyResponse for Diff {
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64> {
+        let z = z_inv(freq, sr);
+        match self.method {
+            IntegrationMethod::ForwardEuler => 1.0 - z,
+            IntegrationMethod::Trapezoidal => (1.0 - z)*2.0/(1.0 + z),
+            IntegrationMethod::Leaky(time) => {
+                let c = 2.0*time as f64;
+                (1.0 - z)*2.0/((1.0 + c) + z*(1.0 - c))
+            }
+        }
+    }
+}
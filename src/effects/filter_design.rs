@@ -0,0 +1,394 @@
+/*!
+*  Design of steep IIR filters of arbitrary order.
+*
+*  Analog prototypes (Butterworth, Chebyshev I and II, Bessel, elliptic) are
+*  scaled to the cutoff, transformed to the digital domain with the bilinear
+*  transform, and split into cascaded second-order sections.
+*/
+
+use std::f64::consts;
+
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Float;
+
+use crate::effects::filter::{Biquad, BiquadCoefs, BiquadForm, FrequencyResponse};
+
+type C64 = Complex<f64>;
+
+/// Analog prototype of a filter.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Prototype {
+    /// Maximally flat passband. The cutoff is at -3dB.
+    Butterworth,
+    /// Passband ripple of `ripple` decibels, steeper than Butterworth. The
+    /// cutoff is the edge of the passband, i.e. where the response falls
+    /// below the ripple.
+    Chebyshev1 { ripple: f64 },
+    /// Flat passband and stopband attenuation of at least `attenuation`
+    /// decibels. The cutoff is the edge of the stopband.
+    Chebyshev2 { attenuation: f64 },
+    /// Maximally flat group delay, with the gentlest slope. The cutoff is at
+    /// -3dB.
+    Bessel,
+    /// Passband ripple of `ripple` decibels and stopband attenuation of
+    /// `attenuation` decibels, with the steepest possible transition. The
+    /// cutoff is the edge of the passband.
+    Elliptic { ripple: f64, attenuation: f64 },
+}
+
+/// Which side of the cutoff is kept.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Band {
+    Lowpass,
+    Highpass,
+}
+
+/// Design a digital filter as cascaded second-order sections.
+/// # Parameters
+/// - prototype: analog prototype
+/// - band: lowpass or highpass
+/// - order: order of the filter, i.e. number of poles
+/// - cutoff: cutoff frequency in hertz, see `Prototype` for its meaning
+/// - sr: sample rate in hertz
+/// # Returns
+/// - `(order + 1) / 2` sections, to be run in series. With odd orders the
+///   last section is first-order.
+pub fn design(prototype: Prototype, band: Band, order: usize, cutoff: f64, sr: f64) -> Vec<BiquadCoefs<f64>> {
+    let order = order.max(1);
+    let (zeros, poles, dc_gain) = match prototype {
+        Prototype::Butterworth => butterworth(order),
+        Prototype::Chebyshev1 { ripple } => chebyshev1(order, ripple),
+        Prototype::Chebyshev2 { attenuation } => chebyshev2(order, attenuation),
+        Prototype::Bessel => bessel(order),
+        Prototype::Elliptic { ripple, attenuation } => elliptic(order, ripple, attenuation),
+    };
+
+    // Step 1: scale the prototype to the prewarped cutoff
+    let wc = 2.0*sr*(consts::PI*cutoff.clamp(1e-3, 0.4999*sr)/sr).tan();
+    let (zeros, poles): (Vec<C64>, Vec<C64>) = match band {
+        Band::Lowpass => (
+            zeros.iter().map(|z| z*wc).collect(),
+            poles.iter().map(|p| p*wc).collect(),
+        ),
+        Band::Highpass => (
+            // zeros at infinity map to zeros at DC
+            zeros.iter().map(|z| wc/z)
+                .chain((zeros.len()..poles.len()).map(|_| C64::new(0.0, 0.0)))
+                .collect(),
+            poles.iter().map(|p| wc/p).collect(),
+        ),
+    };
+
+    // Step 2: bilinear transform, zeros at infinity map to Nyquist
+    let fs2 = 2.0*sr;
+    let bilinear = |s: &C64| (fs2 + s) / (fs2 - s);
+    let poles: Vec<C64> = poles.iter().map(bilinear).collect();
+    let zeros: Vec<C64> = zeros.iter().map(bilinear)
+        .chain((zeros.len()..poles.len()).map(|_| C64::new(-1.0, 0.0)))
+        .collect();
+
+    // Step 3: pair poles and zeros into sections
+    let mut sections = pair_sections(&zeros, &poles);
+
+    // Step 4: set the gain in the passband
+    let z_ref = match band {
+        Band::Lowpass => 1.0,
+        Band::Highpass => -1.0,
+    };
+    let gain: f64 = sections.iter()
+        .map(|c| (c.b0 + c.b1*z_ref + c.b2) / (1.0 + c.a1*z_ref + c.a2))
+        .product();
+    let first = &mut sections[0];
+    let scale = dc_gain / gain;
+    first.b0 *= scale;
+    first.b1 *= scale;
+    first.b2 *= scale;
+    return sections;
+}
+
+/// Cascade of second-order sections, e.g. from `design`.
+pub struct Cascade<T> {
+    sections: Vec<Biquad<T>>,
+}
+
+impl<T: Float> Cascade<T> {
+    /// Create a cascade from the coefficients of its sections.
+    pub fn new(coefs: &[BiquadCoefs<f64>]) -> Self {
+        let mut ret = Self {
+            sections: Vec::new(),
+        };
+        ret.set_coefs(coefs);
+        return ret;
+    }
+
+    /// Set the coefficients of the sections. The state of the filter is kept
+    /// if the number of sections is unchanged, so that it can be retuned
+    /// while running.
+    pub fn set_coefs(&mut self, coefs: &[BiquadCoefs<f64>]) {
+        if self.sections.len() != coefs.len() {
+            self.sections = coefs.iter()
+                .map(|_| Biquad::new(BiquadForm::TransposedDirectForm2))
+                .collect();
+        }
+        let c = |x: f64| T::from(x).unwrap();
+        for (bq, s) in self.sections.iter_mut().zip(coefs) {
+            bq.set_coefs(BiquadCoefs {
+                b0: c(s.b0),
+                b1: c(s.b1),
+                b2: c(s.b2),
+                a1: c(s.a1),
+                a2: c(s.a2),
+            });
+        }
+    }
+
+    /// Design a filter and create a cascade from it, see `design`.
+    pub fn design(prototype: Prototype, band: Band, order: usize, cutoff: f64, sr: f64) -> Self {
+        Self::new(&design(prototype, band, order, cutoff, sr))
+    }
+
+    /// Filter a single sample.
+    pub fn filter(&mut self, x: T) -> T {
+        self.sections.iter_mut().fold(x, |acc, s| s.filter(acc))
+    }
+
+    /// Reset the state of the filter.
+    pub fn reset(&mut self) {
+        self.sections.iter_mut().for_each(|s| s.reset());
+    }
+}
+
+impl<T: Float> FrequencyResponse for Cascade<T> {
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64> {
+        self.sections.iter().map(|s| s.response(freq, sr)).product()
+    }
+}
+
+/// Zeros, poles and gain at DC of an analog prototype.
+type Zpk = (Vec<C64>, Vec<C64>, f64);
+
+fn butterworth(n: usize) -> Zpk {
+    let poles = (0..n).map(|k| {
+        let theta = consts::PI*(2*k + n + 1) as f64/(2*n) as f64;
+        C64::new(theta.cos(), theta.sin())
+    }).collect();
+    (Vec::new(), poles, 1.0)
+}
+
+fn chebyshev1(n: usize, ripple: f64) -> Zpk {
+    let eps = (10.0f64.powf(ripple.max(1e-6)/10.0) - 1.0).sqrt();
+    let mu = (1.0/eps).asinh()/n as f64;
+    let poles = (0..n).map(|k| {
+        let theta = consts::PI*(2*k + 1) as f64/(2*n) as f64;
+        C64::new(-mu.sinh()*theta.sin(), mu.cosh()*theta.cos())
+    }).collect();
+    let gain = if n.is_multiple_of(2) { 1.0/(1.0 + eps*eps).sqrt() } else { 1.0 };
+    (Vec::new(), poles, gain)
+}
+
+fn chebyshev2(n: usize, attenuation: f64) -> Zpk {
+    let eps = 1.0/(10.0f64.powf(attenuation.max(1e-6)/10.0) - 1.0).sqrt();
+    let mu = (1.0/eps).asinh()/n as f64;
+    let thetas = (0..n).map(|k| consts::PI*(2*k + 1) as f64/(2*n) as f64);
+    let zeros = thetas.clone()
+        .filter(|theta| (theta - consts::FRAC_PI_2).abs() > 1e-9)
+        .map(|theta| C64::new(0.0, 1.0/theta.cos()))
+        .collect();
+    let poles = thetas
+        .map(|theta| C64::new(-mu.sinh()*theta.sin(), mu.cosh()*theta.cos()).inv())
+        .collect();
+    (zeros, poles, 1.0)
+}
+
+fn bessel(n: usize) -> Zpk {
+    // coefficients of the reverse Bessel polynomial, lowest order first
+    let mut coefs = vec![0.0f64; n + 1];
+    for (k, c) in coefs.iter_mut().enumerate() {
+        let ln_c = ln_factorial(2*n - k) - ln_factorial(k) - ln_factorial(n - k)
+            - (n - k) as f64*consts::LN_2;
+        *c = ln_c.exp();
+    }
+    let mut poles = polynomial_roots(&coefs);
+
+    // normalize the poles so that the response is at -3dB at 1 rad/s
+    let magnitude = |poles: &[C64], w: f64| poles.iter()
+        .map(|p| (p / (C64::new(0.0, w) - p)).norm())
+        .product::<f64>();
+    let (mut lo, mut hi) = (1e-3, 1e3);
+    for _ in 0..200 {
+        let mid = (lo*hi).sqrt();
+        if magnitude(&poles, mid) > consts::FRAC_1_SQRT_2 { lo = mid } else { hi = mid }
+    }
+    poles.iter_mut().for_each(|p| *p /= lo);
+    (Vec::new(), poles, 1.0)
+}
+
+fn elliptic(n: usize, ripple: f64, attenuation: f64) -> Zpk {
+    // from S. J. Orfanidis, "Lecture Notes on Elliptic Filter Design"
+    let ep = (10.0f64.powf(ripple.max(1e-6)/10.0) - 1.0).sqrt();
+    let es = (10.0f64.powf(attenuation.max(ripple + 1e-3)/10.0) - 1.0).sqrt();
+    let k1 = ep/es;
+    let k = ellipdeg(n, k1);
+    let j = C64::new(0.0, 1.0);
+
+    let v0 = (-j*asne(j/ep, k1)/n as f64).re;
+    let mut zeros = Vec::new();
+    let mut poles = Vec::new();
+    for i in 1..=n/2 {
+        let u = C64::new((2*i - 1) as f64/n as f64, 0.0);
+        let zeta = cde(u, k);
+        let z = j/(zeta*k);
+        let p = j*cde(u - j*v0, k);
+        zeros.push(z);
+        zeros.push(z.conj());
+        poles.push(p);
+        poles.push(p.conj());
+    }
+    if n % 2 == 1 {
+        poles.push(j*sne(j*v0, k));
+    }
+    let gain = if n.is_multiple_of(2) { 1.0/(1.0 + ep*ep).sqrt() } else { 1.0 };
+    (zeros, poles, gain)
+}
+
+/// Descending Landen sequence of elliptic moduli, starting from `k`.
+fn landen(k: f64) -> Vec<f64> {
+    let mut v = Vec::new();
+    let mut k = k;
+    while k > 1e-15 && v.len() < 32 {
+        let kp = (1.0 - k*k).sqrt();
+        k = (k/(1.0 + kp)).powi(2);
+        v.push(k);
+    }
+    return v;
+}
+
+/// Jacobi elliptic function cd(uK, k), with K the complete elliptic integral.
+fn cde(u: C64, k: f64) -> C64 {
+    let mut w = (u*consts::FRAC_PI_2).cos();
+    for v in landen(k).iter().rev() {
+        w = w*(1.0 + v) / (w*w*v + 1.0);
+    }
+    return w;
+}
+
+/// Jacobi elliptic function sn(uK, k), with K the complete elliptic integral.
+fn sne(u: C64, k: f64) -> C64 {
+    let mut w = (u*consts::FRAC_PI_2).sin();
+    for v in landen(k).iter().rev() {
+        w = w*(1.0 + v) / (w*w*v + 1.0);
+    }
+    return w;
+}
+
+/// Inverse of `sne`.
+fn asne(w: C64, k: f64) -> C64 {
+    let v = landen(k);
+    let mut w = w;
+    let mut v1 = k;
+    for vi in v.iter() {
+        w = w / ((C64::new(1.0, 0.0) - w*w*v1*v1).sqrt() + 1.0) * 2.0/(1.0 + vi);
+        v1 = *vi;
+    }
+    return C64::new(1.0, 0.0) - w.acos()*consts::FRAC_2_PI;
+}
+
+/// Solve the degree equation of elliptic filters, i.e. find the selectivity
+/// modulus from the order and the discrimination modulus `k1`.
+fn ellipdeg(n: usize, k1: f64) -> f64 {
+    let k1p = (1.0 - k1*k1).sqrt();
+    let prod: f64 = (1..=n/2)
+        .map(|i| sne(C64::new((2*i - 1) as f64/n as f64, 0.0), k1p).re.powi(4))
+        .product();
+    let kp = k1p.powi(n as i32)*prod;
+    (1.0 - kp*kp).sqrt()
+}
+
+fn ln_factorial(n: usize) -> f64 {
+    (2..=n).map(|k| (k as f64).ln()).sum()
+}
+
+/// Roots of a polynomial with real coefficients (lowest order first), with
+/// the Durand-Kerner method.
+fn polynomial_roots(coefs: &[f64]) -> Vec<C64> {
+    let n = coefs.len() - 1;
+    let lead = coefs[n];
+    let eval = |x: C64| coefs.iter().rev().fold(C64::new(0.0, 0.0), |acc, c| acc*x + c/lead);
+    let mut roots: Vec<C64> = (0..n).map(|k| C64::new(0.4, 0.9).powi(k as i32)*(n as f64)).collect();
+    for _ in 0..500 {
+        let mut delta = 0.0;
+        for i in 0..n {
+            let denom = (0..n).filter(|j| *j != i)
+                .fold(C64::new(1.0, 0.0), |acc, j| acc*(roots[i] - roots[j]));
+            let step = eval(roots[i]) / denom;
+            roots[i] -= step;
+            delta += step.norm();
+        }
+        if delta < 1e-14 {
+            break;
+        }
+    }
+    return roots;
+}
+
+/// Split poles and zeros into second-order sections. Complex roots are kept
+/// with their conjugate, real roots are paired together, and each pole
+/// section gets the closest zero section, starting from the poles closest to
+/// the unit circle.
+fn pair_sections(zeros: &[C64], poles: &[C64]) -> Vec<BiquadCoefs<f64>> {
+    let mut pole_groups = group_roots(poles);
+    let mut zero_groups = group_roots(zeros);
+    pole_groups.sort_by(|a, b| b[0].norm().partial_cmp(&a[0].norm()).unwrap());
+
+    let mut sections = Vec::new();
+    for pg in pole_groups.iter() {
+        // a first-order pole section must get a first-order zero section
+        let best = zero_groups.iter().enumerate()
+            .filter(|(_, zg)| (zg.len() == 1) == (pg.len() == 1) || zero_groups.len() == 1)
+            .min_by(|(_, a), (_, b)| {
+                (a[0] - pg[0]).norm().partial_cmp(&(b[0] - pg[0]).norm()).unwrap()
+            })
+            .map(|(i, _)| i)
+            .unwrap();
+        let zg = zero_groups.remove(best);
+        let (b1, b2) = quadratic(&zg);
+        let (a1, a2) = quadratic(pg);
+        sections.push(BiquadCoefs {
+            b0: 1.0,
+            b1: b1,
+            b2: b2,
+            a1: a1,
+            a2: a2,
+        });
+    }
+    return sections;
+}
+
+/// Group roots in conjugate pairs, real pairs and at most one single real
+/// root. Complex roots are represented by the one with positive imaginary
+/// part first.
+fn group_roots(roots: &[C64]) -> Vec<Vec<C64>> {
+    let mut groups = Vec::new();
+    let mut reals = Vec::new();
+    for r in roots.iter() {
+        if r.im.abs() <= 1e-9*r.norm().max(1.0) {
+            reals.push(C64::new(r.re, 0.0));
+        } else if r.im > 0.0 {
+            groups.push(vec![*r, r.conj()]);
+        }
+    }
+    reals.sort_by(|a, b| a.re.partial_cmp(&b.re).unwrap());
+    for pair in reals.chunks(2) {
+        groups.push(pair.to_vec());
+    }
+    return groups;
+}
+
+/// Coefficients `(c1, c2)` of the monic polynomial `1 + c1 z^-1 + c2 z^-2`
+/// with the given roots.
+fn quadratic(roots: &[C64]) -> (f64, f64) {
+    match roots.len() {
+        1 => (-roots[0].re, 0.0),
+        _ => (-(roots[0] + roots[1]).re, (roots[0]*roots[1]).re),
+    }
+}
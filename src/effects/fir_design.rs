@@ -0,0 +1,450 @@
+/*!
+*  Design of linear-phase FIR filters, and a runtime to apply them.
+*
+*  Kernels are designed in double precision, either with the windowed-sinc
+*  method (using the windows of `fft::windows`) or with the Parks-McClellan
+*  algorithm for equiripple filters, and run with `Fir`.
+*/
+
+use std::f64::consts;
+use std::ops::Range;
+
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Float;
+
+use crate::effects::filter::FrequencyResponse;
+use crate::fft::windows::Window;
+
+/// Response of a windowed-sinc filter. Frequencies are in hertz.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FirBand {
+    Lowpass(f64),
+    Highpass(f64),
+    /// Passband between the two frequencies.
+    Bandpass(f64, f64),
+    /// Stopband between the two frequencies.
+    Bandstop(f64, f64),
+}
+
+/// Design a linear-phase filter with the windowed-sinc method.
+/// # Parameters
+/// - band: response of the filter
+/// - len: number of taps. Highpass and bandstop filters need an odd length,
+///   and are made one tap longer if it is even.
+/// - window: window applied to the ideal response. Wider windows give a
+///   wider transition band and a stronger stopband attenuation.
+/// - sr: sample rate in hertz
+/// # Returns
+/// - the taps, normalized to unity gain in the passband
+pub fn windowed_sinc(band: FirBand, len: usize, window: Window, sr: f64) -> Vec<f64> {
+    let len = match band {
+        FirBand::Highpass(_) | FirBand::Bandstop(..) => len | 1,
+        _ => len.max(1),
+    };
+    let win = window.symmetric(len);
+    let center = (len - 1) as f64 / 2.0;
+    let lowpass = |cutoff: f64| -> Vec<f64> {
+        let fc = (cutoff / sr).clamp(1e-6, 0.5);
+        let h: Vec<f64> = win.iter().enumerate().map(|(i, w)| {
+            let t = i as f64 - center;
+            let sinc = if t == 0.0 { 2.0*fc } else { (consts::TAU*fc*t).sin()/(consts::PI*t) };
+            sinc*w
+        }).collect();
+        let dc_gain: f64 = h.iter().sum();
+        h.iter().map(|x| x/dc_gain).collect()
+    };
+    // spectral inversion, for odd lengths
+    let invert = |mut h: Vec<f64>| {
+        h.iter_mut().for_each(|x| *x = -*x);
+        h[len/2] += 1.0;
+        h
+    };
+    let bandpass = |low: f64, high: f64| -> Vec<f64> {
+        lowpass(high).iter().zip(lowpass(low)).map(|(h, l)| h - l).collect()
+    };
+    match band {
+        FirBand::Lowpass(cutoff) => lowpass(cutoff),
+        FirBand::Highpass(cutoff) => invert(lowpass(cutoff)),
+        FirBand::Bandpass(low, high) => bandpass(low, high),
+        FirBand::Bandstop(low, high) => invert(bandpass(low, high)),
+    }
+}
+
+/// Design a Hilbert transformer, shifting the phase of all frequencies by
+/// -90 degrees (on top of the delay of `len/2` samples).
+/// # Parameters
+/// - len: number of taps, made odd if it is even
+/// - window: window applied to the ideal response. The gain falls off near
+///   DC and Nyquist, over a band that narrows with longer kernels.
+pub fn hilbert(len: usize, window: Window) -> Vec<f64> {
+    let len = len.max(3) | 1;
+    let center = (len/2) as isize;
+    return window.symmetric(len).iter().enumerate().map(|(i, w)| {
+        let t = i as isize - center;
+        if t % 2 == 0 { 0.0 } else { 2.0/(consts::PI*t as f64)*w }
+    }).collect();
+}
+
+/// Band of a Parks-McClellan design. Frequencies are in hertz.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RemezBand {
+    pub start: f64,
+    pub end: f64,
+    /// Desired gain in the band, e.g. 1 for a passband and 0 for a stopband.
+    pub gain: f64,
+    /// Relative weight of the error in the band. With weights of 1 and 10,
+    /// the ripple of the second band is 10 times smaller.
+    pub weight: f64,
+}
+
+/// Iterations of the Remez exchange before giving up on a design.
+const MAX_REMEZ_ITERATIONS: usize = 100;
+
+/// Largest extremal set for which the Remez exchange starts from an even
+/// spread over the grid.
+const MAX_UNSCALED_REMEZ_SIZE: usize = 32;
+
+/// Point of the dense grid of the Remez exchange.
+struct GridPoint {
+    x: f64,
+    gain: f64,
+    weight: f64,
+    band: usize,
+}
+
+/// Polynomial in `cos(w)`, interpolated through a set of points with the
+/// barycentric Lagrange formula.
+struct Interpolation {
+    x: Vec<f64>,
+    y: Vec<f64>,
+    b: Vec<f64>,
+}
+
+impl Interpolation {
+    fn new(x: &[f64], y: &[f64]) -> Self {
+        Self {
+            x: x.to_vec(),
+            y: y.to_vec(),
+            b: barycentric_weights(x),
+        }
+    }
+
+    fn eval(&self, x: f64) -> f64 {
+        let (mut num, mut den) = (0.0, 0.0);
+        for ((x_k, y_k), b_k) in self.x.iter().zip(&self.y).zip(&self.b) {
+            let d = x - x_k;
+            if d.abs() < 1e-14 {
+                return *y_k;
+            }
+            num += b_k/d*y_k;
+            den += b_k/d;
+        }
+        return num/den;
+    }
+}
+
+/// Weights of the barycentric Lagrange formula. The products of the
+/// differences under- or overflow for long filters, so they are summed as
+/// logarithms, and all the weights are scaled by the same factor, which
+/// cancels out in the formula.
+fn barycentric_weights(x: &[f64]) -> Vec<f64> {
+    let logs: Vec<(f64, f64)> = x.iter().enumerate().map(|(k, x_k)| {
+        x.iter().enumerate()
+            .filter(|(j, _)| *j != k)
+            .fold((0.0, 1.0), |(log, sign), (_, x_j)| {
+                let d = x_k - x_j;
+                (log - d.abs().ln(), sign*d.signum())
+            })
+    }).collect();
+    let max = logs.iter().fold(f64::NEG_INFINITY, |acc, (log, _)| acc.max(*log));
+    logs.iter().map(|(log, sign)| sign*(log - max).exp()).collect()
+}
+
+/// Initial extremal set of the Remez exchange, scaled from the final set of
+/// a shorter filter. Each band gets a share of the points in proportion to
+/// the points it had, spread over the band like them.
+/// # Parameters
+/// - ranges: indices in the grid of each band
+/// - small: indices in the grid of the set of the shorter filter, in
+///   ascending order
+/// - r: size of the new set
+/// # Returns
+/// - the indices of the new set, or `None` if a band has too few points of
+///   the grid
+fn scale_extremals(ranges: &[Range<usize>], small: &[usize], r: usize) -> Option<Vec<usize>> {
+    let in_band = |range: &Range<usize>| -> Vec<f64> {
+        small.iter().filter(|i| range.contains(i)).map(|i| *i as f64).collect()
+    };
+    let mut counts: Vec<usize> = ranges.iter()
+        .map(|range| (in_band(range).len()*r) as f64/small.len() as f64)
+        .map(|count| count.round() as usize)
+        .collect();
+    // the rounding errors go to the band with the most points
+    let total: usize = counts.iter().sum();
+    let largest = (0..counts.len()).max_by_key(|b| counts[*b])?;
+    counts[largest] = (counts[largest] + r).checked_sub(total)?;
+
+    let mut scaled: Vec<usize> = Vec::with_capacity(r);
+    for (range, count) in ranges.iter().zip(counts) {
+        let mut old = in_band(range);
+        if old.len() < 2 {
+            old = vec![range.start as f64, (range.end - 1) as f64];
+        }
+        for k in 0..count {
+            let t = if count == 1 { 0.5 } else { k as f64/(count - 1) as f64 }*(old.len() - 1) as f64;
+            let i = (t as usize).min(old.len() - 2);
+            let j = (old[i] + (t - i as f64)*(old[i + 1] - old[i])).round() as usize;
+            let j = scaled.last().map_or(j, |last| j.max(last + 1));
+            if j >= range.end {
+                return None;
+            }
+            scaled.push(j);
+        }
+    }
+    return Some(scaled);
+}
+
+/// New extremal set of the Remez exchange: the local extrema of the error
+/// within each band, with alternating signs. Where there are too many, the
+/// smallest ones are dropped.
+/// # Returns
+/// - the indices of `r` extrema in the grid, or `None` if there are not
+///   enough of them
+fn exchange(grid: &[GridPoint], err: &[f64], r: usize) -> Option<Vec<usize>> {
+    let mut candidates: Vec<usize> = Vec::new();
+    for j in 0..grid.len() {
+        let neighbour = |k: Option<usize>| k.filter(|k| *k < grid.len() && grid[*k].band == grid[j].band);
+        let is_extremum = [neighbour(j.checked_sub(1)), neighbour(Some(j + 1))].iter()
+            .flatten()
+            .all(|&k| err[j].abs() >= err[k].abs() || err[j].signum() != err[k].signum());
+        if !is_extremum {
+            continue;
+        }
+        // of consecutive extrema with the same sign, keep the largest
+        match candidates.last() {
+            Some(&last) if err[last].signum() == err[j].signum() => {
+                if err[j].abs() > err[last].abs() {
+                    *candidates.last_mut().unwrap() = j;
+                }
+            }
+            _ => candidates.push(j),
+        }
+    }
+
+    // dropping an extremum inside the set leaves its neighbours with the
+    // same sign, so the smaller of the two goes with it. A single extra
+    // extremum can only be dropped at an end.
+    while candidates.len() > r {
+        let last = candidates.len() - 1;
+        let size = |k: usize| err[candidates[k]].abs();
+        let smallest = (0..=last).min_by(|a, b| size(*a).total_cmp(&size(*b))).unwrap();
+        if candidates.len() == r + 1 || smallest == 0 || smallest == last {
+            let end = if size(0) < size(last) { 0 } else { last };
+            candidates.remove(end);
+        } else {
+            let neighbour = if size(smallest - 1) < size(smallest + 1) { smallest - 1 } else { smallest + 1 };
+            candidates.remove(smallest.max(neighbour));
+            candidates.remove(smallest.min(neighbour));
+        }
+    }
+    let alternating = candidates.windows(2).all(|k| err[k[0]]*err[k[1]] < 0.0);
+    if candidates.len() < r || !alternating {
+        return None;
+    }
+    return Some(candidates);
+}
+
+/// Design an equiripple linear-phase filter with the Parks-McClellan
+/// algorithm. The bands must not overlap or touch; the gaps between them are
+/// transition bands.
+/// # Parameters
+/// - len: number of taps, made odd if it is even
+/// - bands: bands of the response, in ascending order
+/// - sr: sample rate in hertz
+/// # Returns
+/// - the taps, or `None` if the bands are empty or too narrow for the
+///   length of the filter, or if the design does not converge. It does not
+///   converge either when the ripple would be too small for double
+///   precision, around 1e-9.
+pub fn remez(len: usize, bands: &[RemezBand], sr: f64) -> Option<Vec<f64>> {
+    let len = len.max(3) | 1;
+    let half = (len - 1)/2;
+
+    // Step 1: bands in w = 2*pi*f/sr
+    let to_w = |f: f64| consts::TAU*f.clamp(0.0, 0.5*sr)/sr;
+    let bands: Vec<RemezBand> = bands.iter()
+        .map(|b| RemezBand { start: to_w(b.start), end: to_w(b.end), ..*b })
+        .collect();
+    let total_width: f64 = bands.iter().map(|b| (b.end - b.start).max(0.0)).sum();
+    if bands.is_empty() || total_width <= 0.0 {
+        return None;
+    }
+
+    // Step 2: polynomial in cos(w) with an equiripple error
+    let (interp, _) = equiripple(&bands, half + 2)?;
+
+    // Step 3: sample the zero-phase response and take its inverse DFT
+    let n = len as f64;
+    let samples: Vec<f64> = (0..len).map(|k| interp.eval((consts::TAU*k as f64/n).cos())).collect();
+    return Some((0..len).map(|i| {
+        let t = i as f64 - half as f64;
+        samples.iter().enumerate()
+            .map(|(k, a)| a*(consts::TAU*k as f64*t/n).cos())
+            .sum::<f64>()/n
+    }).collect());
+}
+
+/// Remez exchange over a dense grid of the bands.
+/// # Parameters
+/// - bands: bands of the response, with frequencies in radians per sample
+/// - r: size of the extremal set, i.e. the degree of the polynomial plus 2
+/// # Returns
+/// - the polynomial in `cos(w)`, and the `cos(w)` of the final extremal
+///   set, or `None` if the exchange does not converge
+fn equiripple(bands: &[RemezBand], r: usize) -> Option<(Interpolation, Vec<f64>)> {
+    let total_width: f64 = bands.iter().map(|b| (b.end - b.start).max(0.0)).sum();
+    let step = total_width/(16*r) as f64;
+    let mut grid = Vec::new();
+    let mut ranges = Vec::new();
+    for b in bands {
+        let n = (((b.end - b.start)/step).ceil() as usize).max(1);
+        ranges.push(grid.len()..grid.len() + n + 1);
+        for j in 0..=n {
+            grid.push(GridPoint {
+                x: (b.start + (b.end - b.start)*j as f64/n as f64).cos(),
+                gain: b.gain,
+                weight: b.weight,
+                band: ranges.len() - 1,
+            });
+        }
+    }
+    if grid.len() < r {
+        return None;
+    }
+
+    // the usual initial extremal set, spread evenly over the grid, has a
+    // levelled error far below the final one on long filters, which gets
+    // lost in the rounding errors. Scale the final set of a shorter filter
+    // instead, which is already close to the final one.
+    let mut extremals: Vec<usize> = (0..r).map(|k| k*(grid.len() - 1)/(r - 1)).collect();
+    if r > MAX_UNSCALED_REMEZ_SIZE {
+        if let Some((_, x)) = equiripple(bands, r/2 + 1) {
+            // closest points of this grid, which runs down in x
+            let nearest: Vec<usize> = x.iter().map(|x| {
+                let j = grid.partition_point(|g| g.x > *x).min(grid.len() - 1);
+                if j > 0 && grid[j - 1].x - x < x - grid[j].x { j - 1 } else { j }
+            }).collect();
+            if let Some(scaled) = scale_extremals(&ranges, &nearest, r) {
+                extremals = scaled;
+            }
+        }
+    }
+
+    let mut iterations = 0;
+    loop {
+        iterations += 1;
+        if iterations > MAX_REMEZ_ITERATIONS {
+            return None;
+        }
+        // the points must be distinct, which they are not where two bands
+        // touch
+        let x: Vec<f64> = extremals.iter().map(|&i| grid[i].x).collect();
+        if x.windows(2).any(|x| x[0] - x[1] < 1e-12) {
+            return None;
+        }
+        let b = barycentric_weights(&x);
+        let sign = |k: usize| if k.is_multiple_of(2) { 1.0 } else { -1.0 };
+        let num: f64 = extremals.iter().enumerate().map(|(k, &i)| b[k]*grid[i].gain).sum();
+        let den: f64 = extremals.iter().enumerate().map(|(k, &i)| b[k]*sign(k)/grid[i].weight).sum();
+        let delta = num/den;
+        if !delta.is_finite() {
+            return None;
+        }
+        let y: Vec<f64> = extremals.iter().enumerate()
+            .map(|(k, &i)| grid[i].gain - sign(k)*delta/grid[i].weight)
+            .collect();
+        // the polynomial goes through all the points but one, where the
+        // error is delta as well. Leaving out a point in the middle rather
+        // than at an end keeps the error there an interpolation, which is
+        // much more accurate than an extrapolation.
+        let (x_interp, y_interp): (Vec<f64>, Vec<f64>) = x.iter().zip(&y).enumerate()
+            .filter(|(k, _)| *k != r/2)
+            .map(|(_, (x, y))| (*x, *y))
+            .unzip();
+        let interp = Interpolation::new(&x_interp, &y_interp);
+
+        let err: Vec<f64> = grid.iter().map(|g| g.weight*(g.gain - interp.eval(g.x))).collect();
+        let max_err = err.iter().fold(0.0f64, |acc, e| acc.max(e.abs()));
+        if !max_err.is_finite() {
+            return None;
+        }
+        if max_err - delta.abs() <= 1e-9*max_err {
+            return Some((interp, x));
+        }
+        // the exchange gets stuck once the rounding errors outweigh the
+        // improvements, which is fine if the error is close to equiripple
+        let candidates = exchange(&grid, &err, r)?;
+        if candidates == extremals {
+            return if max_err - delta.abs() <= 1e-3*max_err { Some((interp, x)) } else { None };
+        }
+        extremals = candidates;
+    }
+}
+
+/// FIR filter, in single (`f32`) or double (`f64`) precision.
+///
+/// The input history is stored twice, so that each output is a single dot
+/// product over contiguous memory, which the compiler can vectorize.
+pub struct Fir<T> {
+    taps: Vec<T>,
+    history: Vec<T>,
+    pos: usize,
+}
+
+impl<T: Float> Fir<T> {
+    /// Create a filter from its taps, e.g. from `windowed_sinc` or `remez`.
+    pub fn new(taps: &[f64]) -> Self {
+        let taps: Vec<T> = taps.iter().map(|t| T::from(*t).unwrap()).collect();
+        let len = taps.len().max(1);
+        Self {
+            taps: taps,
+            history: vec![T::zero(); 2*len],
+            pos: 0,
+        }
+    }
+
+    /// Filter a single sample.
+    #[inline]
+    pub fn filter(&mut self, x: T) -> T {
+        let len = self.taps.len();
+        if len == 0 {
+            return T::zero();
+        }
+        // the history runs backwards, so that it lines up with the taps
+        self.pos = if self.pos == 0 { len - 1 } else { self.pos - 1 };
+        self.history[self.pos] = x;
+        self.history[self.pos + len] = x;
+        return self.taps.iter()
+            .zip(&self.history[self.pos..self.pos + len])
+            .fold(T::zero(), |acc, (h, x)| acc + *h * *x);
+    }
+
+    /// Latency of a linear-phase filter, in samples.
+    pub fn latency(&self) -> f64 {
+        (self.taps.len().max(1) - 1) as f64/2.0
+    }
+
+    /// Reset the state of the filter.
+    pub fn reset(&mut self) {
+        self.history.iter_mut().for_each(|x| *x = T::zero());
+    }
+}
+
+impl<T: Float> FrequencyResponse for Fir<T> {
+    fn response(&self, freq: f64, sr: f64) -> Complex<f64> {
+        let w = consts::TAU*freq/sr;
+        return self.taps.iter().enumerate()
+            .map(|(n, h)| Complex::new(0.0, -w*n as f64).exp()*h.to_f64().unwrap())
+            .sum();
+    }
+}
@@ -0,0 +1,160 @@
+use crate::effects::delay::RingBuffer;
+use crate::effects::filter::LpComb;
+
+// Tuning of the reference implementation by Jezar at Dreampoint, for a
+// sample rate of 44.1kHz.
+const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+const ALLPASS_FB: f32 = 0.5;
+const FIXED_GAIN: f32 = 0.015;
+const SCALE_WET: f32 = 3.0;
+const SCALE_DRY: f32 = 2.0;
+const SCALE_DAMP: f32 = 0.4;
+const SCALE_ROOM: f32 = 0.28;
+const OFFSET_ROOM: f32 = 0.7;
+
+/// The diffusion stage of the reference algorithm. Note that it is not a
+/// true allpass (unlike `filter::AllPass`), but it must be kept as is for the
+/// output to match the reference.
+struct Diffuser {
+    line: RingBuffer,
+    len: usize,
+}
+
+impl Diffuser {
+    fn filter(&mut self, input: f32) -> f32 {
+        let buf_out = self.line.tap(self.len - 1);
+        self.line.push(input + buf_out*ALLPASS_FB);
+        return buf_out - input;
+    }
+}
+
+/// Freeverb reverb: 8 parallel lowpass-feedback combs followed by 4 serial
+/// diffusers per channel. The output matches the reference implementation
+/// at 44.1kHz; at other sample rates the delay lengths are scaled.
+///
+/// All parameters are in [0, 1], with the same mapping as the reference.
+pub struct Freeverb {
+    combs_l: Vec<LpComb>,
+    combs_r: Vec<LpComb>,
+    diffusers_l: Vec<Diffuser>,
+    diffusers_r: Vec<Diffuser>,
+    room_size: f32,
+    damp: f32,
+    wet: f32,
+    dry: f32,
+    width: f32,
+    freeze: bool,
+    gain: f32,
+    wet_1: f32,
+    wet_2: f32,
+}
+
+impl Freeverb {
+    /// Create a new reverb with the default parameters of the reference.
+    /// # Parameters
+    /// - sr: sample rate in hertz
+    pub fn new(sr: u32) -> Self {
+        let scale = |len: usize| ((len as f32 * sr as f32 / 44100.0).round() as usize).max(1);
+        let spread = scale(STEREO_SPREAD);
+        let diffuser = |len: usize| Diffuser {
+            line: RingBuffer::new(len),
+            len: len,
+        };
+        let mut ret = Self {
+            combs_l: COMB_TUNING.iter().map(|l| LpComb::with_len(scale(*l), 0.0, 0.0)).collect(),
+            combs_r: COMB_TUNING.iter().map(|l| LpComb::with_len(scale(*l) + spread, 0.0, 0.0)).collect(),
+            diffusers_l: ALLPASS_TUNING.iter().map(|l| diffuser(scale(*l))).collect(),
+            diffusers_r: ALLPASS_TUNING.iter().map(|l| diffuser(scale(*l) + spread)).collect(),
+            room_size: 0.5,
+            damp: 0.5,
+            wet: 1.0 / SCALE_WET,
+            dry: 0.0,
+            width: 1.0,
+            freeze: false,
+            gain: FIXED_GAIN,
+            wet_1: 0.0,
+            wet_2: 0.0,
+        };
+        ret.update();
+        return ret;
+    }
+
+    /// Set the room size, i.e. the feedback of the combs.
+    pub fn set_room_size(&mut self, room_size: f32) {
+        self.room_size = room_size.clamp(0.0, 1.0);
+        self.update();
+    }
+
+    /// Set the damping of the high frequencies.
+    pub fn set_damp(&mut self, damp: f32) {
+        self.damp = damp.clamp(0.0, 1.0);
+        self.update();
+    }
+
+    /// Set the level of the reverberated signal.
+    pub fn set_wet(&mut self, wet: f32) {
+        self.wet = wet.clamp(0.0, 1.0);
+        self.update();
+    }
+
+    /// Set the level of the input signal.
+    pub fn set_dry(&mut self, dry: f32) {
+        self.dry = dry.clamp(0.0, 1.0);
+    }
+
+    /// Set the stereo width of the reverberated signal.
+    pub fn set_width(&mut self, width: f32) {
+        self.width = width.clamp(0.0, 1.0);
+        self.update();
+    }
+
+    /// Enable or disable freeze mode: the input is muted and the tail is
+    /// sustained indefinitely.
+    pub fn set_freeze(&mut self, freeze: bool) {
+        self.freeze = freeze;
+        self.update();
+    }
+
+    /// Process a stereo sample.
+    /// # Returns
+    /// - left and right outputs, including the dry signal
+    pub fn process(&mut self, l: f32, r: f32) -> (f32, f32) {
+        let input = (l + r)*self.gain;
+
+        let mut out_l = self.combs_l.iter_mut().map(|c| c.filter(input)).sum::<f32>();
+        let mut out_r = self.combs_r.iter_mut().map(|c| c.filter(input)).sum::<f32>();
+        for d in self.diffusers_l.iter_mut() {
+            out_l = d.filter(out_l);
+        }
+        for d in self.diffusers_r.iter_mut() {
+            out_r = d.filter(out_r);
+        }
+
+        let dry = self.dry*SCALE_DRY;
+        return (
+            out_l*self.wet_1 + out_r*self.wet_2 + l*dry,
+            out_r*self.wet_1 + out_l*self.wet_2 + r*dry,
+        );
+    }
+
+    /// Recompute the internal values from the parameters.
+    fn update(&mut self) {
+        let wet = self.wet*SCALE_WET;
+        self.wet_1 = wet*(self.width/2.0 + 0.5);
+        self.wet_2 = wet*((1.0 - self.width)/2.0);
+
+        let (fb, damp) = if self.freeze {
+            self.gain = 0.0;
+            (1.0, 0.0)
+        } else {
+            self.gain = FIXED_GAIN;
+            (self.room_size*SCALE_ROOM + OFFSET_ROOM, self.damp*SCALE_DAMP)
+        };
+        for c in self.combs_l.iter_mut().chain(self.combs_r.iter_mut()) {
+            c.set_fb(fb);
+            c.set_damp(damp);
+        }
+    }
+}
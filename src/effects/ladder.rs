@@ -0,0 +1,135 @@
+use std::f32::consts;
+
+/// Algorithm used by a `Ladder`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LadderModel {
+    /// Huovilainen's model of the Moog transistor ladder, "Non-linear
+    /// digital implementation of the Moog ladder filter" (DAFx 2004). Each
+    /// stage is saturated, and the feedback is averaged over two samples to
+    /// compensate for its unit delay.
+    Huovilainen,
+    /// Zero-delay feedback ladder of four TPT one-poles, after Zavalishin's
+    /// "The Art of VA Filter Design". The feedback loop is solved
+    /// linearly, then the stages are saturated. Stays tuned up to Nyquist.
+    Zdf,
+}
+
+/// Outputs of a `Ladder`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LadderOutputs {
+    /// 2-pole lowpass, 12dB/octave.
+    pub lp2: f32,
+    /// 4-pole lowpass, 24dB/octave.
+    pub lp4: f32,
+}
+
+/// 4-pole Moog-style ladder lowpass filter with tanh saturation in every
+/// stage. The resonance is taken from the 4-pole output, and the filter
+/// self-oscillates at the cutoff frequency when the resonance exceeds 1.
+pub struct Ladder {
+    model: LadderModel,
+    stages: [f32; 4],
+    // tanh of the stages for Huovilainen, states of the integrators for ZDF
+    states: [f32; 4],
+    lp4_z1: f32,
+    g: f32,
+    k: f32,
+    drive: f32,
+    compensation: f32,
+}
+
+impl Ladder {
+    /// Create a new filter, open at 1kHz for a 44.1kHz sample rate, without
+    /// resonance, drive or compensation.
+    pub fn new(model: LadderModel) -> Self {
+        let mut ret = Self {
+            model: model,
+            stages: [0.0; 4],
+            states: [0.0; 4],
+            lp4_z1: 0.0,
+            g: 0.0,
+            k: 0.0,
+            drive: 1.0,
+            compensation: 0.0,
+        };
+        ret.set_params(1000.0, 0.0, 44100.0);
+        return ret;
+    }
+
+    /// Set the cutoff and resonance of the filter.
+    /// # Parameters
+    /// - cutoff: cutoff frequency in hertz
+    /// - res: resonance in [0, 1.2], self-oscillates above 1
+    /// - sr: sample rate in hertz
+    pub fn set_params(&mut self, cutoff: f32, res: f32, sr: f32) {
+        let cutoff = cutoff.clamp(1.0, 0.45*sr);
+        self.g = match self.model {
+            LadderModel::Huovilainen => 1.0 - (-consts::TAU*cutoff/sr).exp(),
+            LadderModel::Zdf => (consts::PI*cutoff/sr).tan(),
+        };
+        self.k = 4.0*res.clamp(0.0, 1.2);
+    }
+
+    /// Set the gain into the saturating stages. The output is scaled back by
+    /// the same amount, so that only the character of the filter changes.
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive.max(1e-3);
+    }
+
+    /// Set the resonance compensation in [0, 1]. The feedback of the ladder
+    /// lowers its passband gain as the resonance rises; at 1 the input is
+    /// boosted to keep the passband of the 4-pole output at unity gain.
+    pub fn set_compensation(&mut self, compensation: f32) {
+        self.compensation = compensation.clamp(0.0, 1.0);
+    }
+
+    /// Filter a sample.
+    pub fn process(&mut self, input: f32) -> LadderOutputs {
+        let x = input*self.drive*(1.0 + self.compensation*self.k);
+        match self.model {
+            LadderModel::Huovilainen => self.tick_huovilainen(x),
+            LadderModel::Zdf => self.tick_zdf(x),
+        }
+        return LadderOutputs {
+            lp2: self.stages[1]/self.drive,
+            lp4: self.stages[3]/self.drive,
+        };
+    }
+
+    /// Reset the state of the filter.
+    pub fn reset(&mut self) {
+        self.stages = [0.0; 4];
+        self.states = [0.0; 4];
+        self.lp4_z1 = 0.0;
+    }
+
+    fn tick_huovilainen(&mut self, x: f32) {
+        let (s, t) = (&mut self.stages, &mut self.states);
+        let fb = 0.5*(s[3] + self.lp4_z1);
+        self.lp4_z1 = s[3];
+        let mut stage_in = (x - self.k*fb).tanh();
+        for (stage, stage_tanh) in s.iter_mut().zip(t.iter_mut()) {
+            *stage += self.g*(stage_in - *stage_tanh);
+            *stage_tanh = stage.tanh();
+            stage_in = *stage_tanh;
+        }
+    }
+
+    fn tick_zdf(&mut self, x: f32) {
+        // each one-pole is y = G*x + (1 - G)*s, so the 4-pole output is
+        // G^4*u + sigma and the feedback can be solved for directly
+        let g = self.g/(1.0 + self.g);
+        let s = &mut self.states;
+        let sigma = s.iter().fold(0.0, |acc, s| acc*g + (1.0 - g)*s);
+        let g4 = g*g*g*g;
+        let lp4 = (g4*x + sigma)/(1.0 + self.k*g4);
+        let mut stage_in = (x - self.k*lp4).tanh();
+        for (state, stage) in s.iter_mut().zip(self.stages.iter_mut()) {
+            let v = (stage_in - *state)*g;
+            let y = v + *state;
+            *state = y + v;
+            *stage = y;
+            stage_in = y.tanh();
+        }
+    }
+}
@@ -1,3 +1,13 @@
 pub mod delay;
+pub mod stereo_delay;
 pub mod filter;
-pub mod distortion;
\ No newline at end of file
+pub mod filter_design;
+pub mod fir_design;
+pub mod crossover;
+pub mod ladder;
+pub mod oversampling;
+pub mod distortion;
+pub mod hysteresis;
+pub mod fdn;
+pub mod freeverb;
+pub mod plate;
\ No newline at end of file
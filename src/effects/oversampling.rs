@@ -0,0 +1,307 @@
+/*!
+*  Oversampling, for running nonlinear processing (e.g. `distortion::mu_law`
+*  or `distortion::var_clip`) without aliasing.
+*
+*  The signal is upsampled by cascaded 2x stages, processed at the high rate,
+*  and brought back down by the same stages in reverse. Each stage is a
+*  halfband lowpass in polyphase form: the filter runs at the lower of the two
+*  rates, and half of its work is skipped.
+*
+*  The later stages only have to reject the images of a signal that already
+*  fits in a small part of their band, so their filters are much shorter than
+*  the first one.
+*/
+
+use std::f64::consts;
+use std::mem;
+
+use crate::effects::delay::RingBuffer;
+use crate::effects::filter::FirstOrderAllPass;
+use crate::effects::fir_design::{self, Fir, FirBand};
+use crate::fft::windows::Window;
+
+/// Ratio between the oversampled rate and the base rate.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OversamplingFactor {
+    Two,
+    Four,
+    Eight,
+    Sixteen,
+}
+
+impl OversamplingFactor {
+    /// Ratio between the oversampled rate and the base rate.
+    pub fn ratio(self) -> usize {
+        1 << self.stages()
+    }
+
+    /// Number of 2x stages.
+    fn stages(self) -> usize {
+        match self {
+            OversamplingFactor::Two => 1,
+            OversamplingFactor::Four => 2,
+            OversamplingFactor::Eight => 3,
+            OversamplingFactor::Sixteen => 4,
+        }
+    }
+}
+
+/// Halfband filters used by an `Oversampler`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OversamplingFilter {
+    /// Linear-phase FIR filters, with about 75dB of stopband attenuation.
+    /// The phase is not changed, at the cost of about 55 to 70 samples of
+    /// latency.
+    Fir,
+    /// Polyphase IIR filters, made of two parallel chains of allpasses, with
+    /// over 100dB of stopband attenuation. The latency is only 3 to 5
+    /// samples, but the phase is shifted near the top of the band.
+    Iir,
+}
+
+/// Size `k` of the halfband FIR of each stage, which has `4*k + 3` taps. The
+/// first stage has the narrowest transition band, from 0.45 to 0.55 of the
+/// base rate.
+const FIR_HALF_LENGTHS: [usize; 4] = [27, 10, 7, 5];
+
+/// Number of allpass coefficients and transition bandwidth (relative to the
+/// high rate) of the halfband IIR of each stage.
+const IIR_DESIGNS: [(usize, f64); 4] = [(8, 0.05), (4, 0.25), (3, 0.36), (2, 0.43)];
+
+/// Runs processing at a multiple of the sample rate.
+///
+/// # Example
+/// ```
+/// use rust_dsp_utils::effects::distortion;
+/// use rust_dsp_utils::effects::oversampling::{Oversampler, OversamplingFactor, OversamplingFilter};
+///
+/// let mut os = Oversampler::new(OversamplingFactor::Four, OversamplingFilter::Fir);
+/// let y = os.process(0.5, |x| distortion::var_clip(4.0*x, 0.8));
+/// ```
+pub struct Oversampler {
+    up: Vec<Halfband>,
+    down: Vec<Halfband>,
+    buffer: Vec<f32>,
+    scratch: Vec<f32>,
+    latency: f64,
+}
+
+impl Oversampler {
+    /// Create a new oversampler.
+    /// # Parameters
+    /// - factor: oversampling ratio
+    /// - filter: type of the halfband filters
+    pub fn new(factor: OversamplingFactor, filter: OversamplingFilter) -> Self {
+        let stages = factor.stages();
+        Self {
+            up: (0..stages).map(|s| Halfband::new(filter, s)).collect(),
+            down: (0..stages).map(|s| Halfband::new(filter, s)).collect(),
+            buffer: vec![0.0; factor.ratio()],
+            scratch: vec![0.0; factor.ratio()],
+            // the low rate of stage s is 2^s times the base rate
+            latency: (0..stages).map(|s| halfband_latency(filter, s)/(1 << s) as f64).sum(),
+        }
+    }
+
+    /// Ratio between the oversampled rate and the base rate.
+    pub fn ratio(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Latency of the round trip through `process`, in samples at the base
+    /// rate. May be fractional. With IIR filters the latency depends on the
+    /// frequency; this is the latency at low frequencies.
+    pub fn latency(&self) -> f64 {
+        self.latency
+    }
+
+    /// Process a sample at the oversampled rate.
+    /// # Parameters
+    /// - x: input sample
+    /// - f: processing, called `ratio()` times in a row on the oversampled
+    ///   signal
+    /// # Returns
+    /// - the processed sample, delayed by `latency()`
+    pub fn process<F: FnMut(f32) -> f32>(&mut self, x: f32, mut f: F) -> f32 {
+        self.upsample(x).iter_mut().for_each(|y| *y = f(*y));
+        return self.downsample();
+    }
+
+    /// Upsample a sample, for processing that needs the whole oversampled
+    /// block at once. Call `downsample` once the block has been processed.
+    /// # Returns
+    /// - the `ratio()` oversampled samples, in time order
+    pub fn upsample(&mut self, x: f32) -> &mut [f32] {
+        self.buffer[0] = x;
+        let mut n = 1;
+        for stage in self.up.iter_mut() {
+            for (x, y) in self.buffer[..n].iter().zip(self.scratch.chunks_mut(2)) {
+                let (y0, y1) = stage.upsample(*x);
+                y[0] = y0;
+                y[1] = y1;
+            }
+            mem::swap(&mut self.buffer, &mut self.scratch);
+            n *= 2;
+        }
+        return &mut self.buffer;
+    }
+
+    /// Downsample the block returned by the last call to `upsample`.
+    /// # Returns
+    /// - the sample at the base rate
+    pub fn downsample(&mut self) -> f32 {
+        let mut n = self.buffer.len();
+        for stage in self.down.iter_mut().rev() {
+            for (x, y) in self.buffer[..n].chunks(2).zip(self.scratch.iter_mut()) {
+                *y = stage.downsample(x[0], x[1]);
+            }
+            mem::swap(&mut self.buffer, &mut self.scratch);
+            n /= 2;
+        }
+        return self.buffer[0];
+    }
+
+    /// Reset the state of the filters.
+    pub fn reset(&mut self) {
+        self.up.iter_mut().chain(self.down.iter_mut()).for_each(|s| s.reset());
+    }
+}
+
+/// Halfband lowpass between a rate and twice that rate.
+enum Halfband {
+    /// The taps at an even distance from the center are zero, so the odd
+    /// phase of the filter is a single tap.
+    Fir {
+        even: Fir<f32>,
+        odd: RingBuffer,
+        odd_delay: usize,
+        center: f32,
+    },
+    /// `(A0(z^2) + z^-1*A1(z^2))/2`, where `A0` and `A1` are chains of
+    /// allpasses.
+    Iir {
+        paths: [Vec<FirstOrderAllPass>; 2],
+    },
+}
+
+impl Halfband {
+    fn new(filter: OversamplingFilter, stage: usize) -> Self {
+        match filter {
+            OversamplingFilter::Fir => {
+                let k = FIR_HALF_LENGTHS[stage];
+                let taps = fir_design::windowed_sinc(FirBand::Lowpass(0.25), 4*k + 3, Window::Blackman, 1.0);
+                let even: Vec<f64> = taps.iter().step_by(2).copied().collect();
+                Halfband::Fir {
+                    even: Fir::new(&even),
+                    odd: RingBuffer::new(k + 1),
+                    odd_delay: k,
+                    center: taps[2*k + 1] as f32,
+                }
+            }
+            OversamplingFilter::Iir => {
+                let (count, transition) = IIR_DESIGNS[stage];
+                let coefs = polyphase_iir_coefs(count, transition);
+                let path = |p: usize| -> Vec<FirstOrderAllPass> {
+                    coefs.iter().skip(p).step_by(2).map(|a| FirstOrderAllPass::from_coef(*a as f32)).collect()
+                };
+                Halfband::Iir {
+                    paths: [path(0), path(1)],
+                }
+            }
+        }
+    }
+
+    /// Upsample a sample into two, in time order.
+    #[inline]
+    fn upsample(&mut self, x: f32) -> (f32, f32) {
+        match self {
+            Halfband::Fir { even, odd, odd_delay, center } => {
+                odd.push(x);
+                return (2.0*even.filter(x), 2.0*(*center)*odd.tap(*odd_delay));
+            }
+            Halfband::Iir { paths } => {
+                let y0 = paths[0].iter_mut().fold(x, |acc, ap| ap.filter(acc));
+                let y1 = paths[1].iter_mut().fold(x, |acc, ap| ap.filter(acc));
+                return (y0, y1);
+            }
+        }
+    }
+
+    /// Downsample two samples, in time order, into one.
+    #[inline]
+    fn downsample(&mut self, x0: f32, x1: f32) -> f32 {
+        match self {
+            Halfband::Fir { even, odd, odd_delay, center } => {
+                let y = even.filter(x0) + *center*odd.tap(*odd_delay);
+                odd.push(x1);
+                return y;
+            }
+            Halfband::Iir { paths } => {
+                // the later sample goes through the path without the delay
+                let y0 = paths[0].iter_mut().fold(x1, |acc, ap| ap.filter(acc));
+                let y1 = paths[1].iter_mut().fold(x0, |acc, ap| ap.filter(acc));
+                return 0.5*(y0 + y1);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Halfband::Fir { even, odd, .. } => {
+                even.reset();
+                odd.clear();
+            }
+            Halfband::Iir { paths } => paths.iter_mut().flatten().for_each(|ap| ap.reset()),
+        }
+    }
+}
+
+/// Delay of the round trip through the upsampling and downsampling filters of
+/// a stage, at low frequencies, in samples at the low rate.
+fn halfband_latency(filter: OversamplingFilter, stage: usize) -> f64 {
+    match filter {
+        OversamplingFilter::Fir => (2*FIR_HALF_LENGTHS[stage] + 1) as f64,
+        OversamplingFilter::Iir => {
+            // each allpass delays DC by (1 - a)/(1 + a) samples at the low
+            // rate. Both paths have the same phase at DC, so each filter
+            // delays the signal by the mean of the delays of the paths, plus
+            // half a sample at the high rate for the second path. The
+            // downsampler takes the later sample of each pair, which cancels
+            // out that half sample.
+            let (count, transition) = IIR_DESIGNS[stage];
+            polyphase_iir_coefs(count, transition).iter()
+                .map(|a| (1.0 - a)/(1.0 + a))
+                .sum()
+        }
+    }
+}
+
+/// Coefficients of the allpasses of a polyphase IIR halfband filter, with the
+/// elliptic design of Laurent de Soras' HIIR library. The even coefficients
+/// belong to the first path, the odd ones to the second.
+/// # Parameters
+/// - count: number of coefficients. More coefficients give a narrower
+///   transition band or a stronger stopband attenuation.
+/// - transition: width of the transition band around a quarter of the
+///   sample rate, relative to the sample rate, in (0, 0.5)
+fn polyphase_iir_coefs(count: usize, transition: f64) -> Vec<f64> {
+    let k = ((1.0 - 2.0*transition)*consts::PI/4.0).tan().powi(2);
+    let kk = (1.0 - k*k).powf(0.25);
+    let e = 0.5*(1.0 - kk)/(1.0 + kk);
+    let e4 = e.powi(4);
+    let q = e*(1.0 + e4*(2.0 + e4*(15.0 + 150.0*e4)));
+    let order = (2*count + 1) as f64;
+    let sign = |i: i32| if i % 2 == 0 { 1.0 } else { -1.0 };
+    return (1..=count).map(|c| {
+        let c = c as f64;
+        let num: f64 = (0..32)
+            .map(|i| sign(i)*q.powi(i*(i + 1))*((2*i + 1) as f64*c*consts::PI/order).sin())
+            .sum::<f64>()*q.powf(0.25);
+        let den: f64 = 0.5 + (1..32)
+            .map(|i| sign(i)*q.powi(i*i)*((2*i) as f64*c*consts::PI/order).cos())
+            .sum::<f64>();
+        let ww = (num/den).powi(2);
+        let x = ((1.0 - ww*k)*(1.0 - ww/k)).sqrt()/(1.0 + ww);
+        (1.0 - x)/(1.0 + x)
+    }).collect();
+}
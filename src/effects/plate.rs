@@ -0,0 +1,274 @@
+use std::f32::consts;
+
+use crate::effects::delay::{DelayLine, HeadId, InterpMethod, MixMethod, RingBuffer};
+use crate::utils::math;
+
+/// Sample rate of the constants in Dattorro's paper.
+pub(crate) const REFERENCE_SR: f32 = 29761.0;
+
+/// Maximum pre-delay, in milliseconds.
+const MAX_PRE_DELAY_MS: f32 = 500.0;
+
+/// Maximum modulation depth of the tank allpasses, in milliseconds.
+const MAX_EXCURSION_MS: f32 = 2.0;
+
+// Delay lengths and output taps from "Effect Design Part 1", in samples at
+// the reference sample rate. Index 0 is the half of the tank starting with
+// the 672 allpass, index 1 the half starting with the 908 allpass.
+const INPUT_DIFFUSERS: [f32; 4] = [142.0, 107.0, 379.0, 277.0];
+const TANK_ALLPASS_1: [f32; 2] = [672.0, 908.0];
+const TANK_DELAY_1: [f32; 2] = [4453.0, 4217.0];
+const TANK_ALLPASS_2: [f32; 2] = [1800.0, 2656.0];
+const TANK_DELAY_2: [f32; 2] = [3720.0, 3163.0];
+
+/// Node of a half of the tank read by an output tap.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum TankNode {
+    Delay1,
+    AllPass2,
+    Delay2,
+}
+
+impl TankNode {
+    /// Length of the node, in samples at the reference sample rate.
+    pub(crate) fn len(self, side: usize) -> f32 {
+        match self {
+            TankNode::Delay1 => TANK_DELAY_1[side],
+            TankNode::AllPass2 => TANK_ALLPASS_2[side],
+            TankNode::Delay2 => TANK_DELAY_2[side],
+        }
+    }
+}
+
+/// Output tap into the tank.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) struct OutputTap {
+    pub(crate) side: usize,
+    pub(crate) node: TankNode,
+    /// Distance from the input of the node, in samples.
+    pub(crate) pos: f32,
+    pub(crate) sign: f32,
+}
+
+const fn tap(side: usize, node: TankNode, pos: f32, sign: f32) -> OutputTap {
+    OutputTap { side: side, node: node, pos: pos, sign: sign }
+}
+
+/// Output taps of Table 2 of the paper. Each output mostly reads the half of
+/// the tank opposite to the one it is named after.
+pub(crate) const TAPS: [[OutputTap; 7]; 2] = [
+    [
+        tap(1, TankNode::Delay1, 266.0, 1.0),
+        tap(1, TankNode::Delay1, 2974.0, 1.0),
+        tap(1, TankNode::AllPass2, 1913.0, -1.0),
+        tap(1, TankNode::Delay2, 1996.0, 1.0),
+        tap(0, TankNode::Delay1, 1990.0, -1.0),
+        tap(0, TankNode::AllPass2, 187.0, -1.0),
+        tap(0, TankNode::Delay2, 1066.0, -1.0),
+    ],
+    [
+        tap(0, TankNode::Delay1, 353.0, 1.0),
+        tap(0, TankNode::Delay1, 3627.0, 1.0),
+        tap(0, TankNode::AllPass2, 1228.0, -1.0),
+        tap(0, TankNode::Delay2, 2673.0, 1.0),
+        tap(1, TankNode::Delay1, 2111.0, -1.0),
+        tap(1, TankNode::AllPass2, 335.0, -1.0),
+        tap(1, TankNode::Delay2, 121.0, -1.0),
+    ],
+];
+
+/// Fixed delay with taps.
+struct Delay {
+    line: RingBuffer,
+    len: usize,
+}
+
+impl Delay {
+    fn new(len: usize) -> Self {
+        Self {
+            line: RingBuffer::new(len + 1),
+            len: len.max(1),
+        }
+    }
+
+    /// Write a sample and return the sample delayed by `len` samples.
+    #[inline(always)]
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.line.tap(self.len - 1);
+        self.line.push(x);
+        return y;
+    }
+}
+
+/// Allpass with negative feedback, like `filter::AllPass`, with a
+/// modulatable fractional length and taps into its delay line.
+struct TapAllPass {
+    line: RingBuffer,
+    len: f32,
+}
+
+impl TapAllPass {
+    fn new(len: f32, max_excursion: f32) -> Self {
+        Self {
+            line: RingBuffer::new((len + max_excursion) as usize + 2),
+            len: len.max(1.0),
+        }
+    }
+
+    /// Filter a sample, with the length of the allpass moved by `excursion`
+    /// samples.
+    #[inline(always)]
+    fn process(&mut self, x: f32, fb: f32, excursion: f32) -> f32 {
+        let offset = (self.len + excursion - 1.0).max(0.0);
+        let i = offset as usize;
+        let y_z1 = math::x_fade(self.line.tap(i), offset - i as f32, self.line.tap(i + 1));
+        let v = x - y_z1*fb;
+        self.line.push(v);
+        return y_z1 + v*fb;
+    }
+}
+
+/// Dattorro plate reverb, from "Effect Design Part 1: Reverberator and
+/// Other Filters" (1997). The delay lengths of the paper are scaled from its
+/// 29.761kHz reference to the actual sample rate.
+///
+/// Outputs only the wet signal.
+pub struct PlateReverb {
+    pre_delay: DelayLine,
+    pre_delay_head: HeadId,
+    diffusers: Vec<TapAllPass>,
+    tank_allpass_1: Vec<TapAllPass>,
+    tank_delay_1: Vec<Delay>,
+    tank_allpass_2: Vec<TapAllPass>,
+    tank_delay_2: Vec<Delay>,
+    // output taps, with their positions scaled to the sample rate
+    taps: [Vec<OutputTap>; 2],
+    bandwidth_z1: f32,
+    damping_z1: [f32; 2],
+    tank_out: [f32; 2],
+    lfo_phase: f32,
+    lfo_rate: f32,
+    sr: f32,
+    excursion: f32,
+    bandwidth: f32,
+    input_diffusion: (f32, f32),
+    decay: f32,
+    decay_diffusion: (f32, f32),
+    damping: f32,
+}
+
+impl PlateReverb {
+    /// Create a new reverb, with the parameters suggested in the paper.
+    /// # Parameters
+    /// - sr: sample rate in hertz
+    pub fn new(sr: u32) -> Self {
+        let sr = sr as f32;
+        let scale = sr / REFERENCE_SR;
+        let max_excursion = MAX_EXCURSION_MS/1000.0*sr;
+        let mut pre_delay = DelayLine::new(MAX_PRE_DELAY_MS, sr as u32, InterpMethod::Linear, MixMethod::Sum);
+        let pre_delay_head = pre_delay.add_head(0.0, 1.0);
+        let mut ret = Self {
+            pre_delay: pre_delay,
+            pre_delay_head: pre_delay_head,
+            diffusers: INPUT_DIFFUSERS.iter().map(|l| TapAllPass::new(l*scale, 0.0)).collect(),
+            tank_allpass_1: TANK_ALLPASS_1.iter().map(|l| TapAllPass::new(l*scale, max_excursion)).collect(),
+            tank_delay_1: TANK_DELAY_1.iter().map(|l| Delay::new((l*scale) as usize)).collect(),
+            tank_allpass_2: TANK_ALLPASS_2.iter().map(|l| TapAllPass::new(l*scale, 0.0)).collect(),
+            tank_delay_2: TANK_DELAY_2.iter().map(|l| Delay::new((l*scale) as usize)).collect(),
+            taps: [0, 1].map(|out| TAPS[out].iter().map(|t| OutputTap { pos: (t.pos*scale).floor(), ..*t }).collect()),
+            bandwidth_z1: 0.0,
+            damping_z1: [0.0; 2],
+            tank_out: [0.0; 2],
+            lfo_phase: 0.0,
+            lfo_rate: 0.0,
+            sr: sr,
+            excursion: 0.0,
+            bandwidth: 0.9995,
+            input_diffusion: (0.75, 0.625),
+            decay: 0.5,
+            decay_diffusion: (0.7, 0.5),
+            damping: 0.0005,
+        };
+        debug_assert!(ret.taps.iter().flatten().all(|t| t.pos < (t.node.len(t.side)*scale).floor()));
+        ret.set_modulation(16.0/REFERENCE_SR*1000.0, 1.0);
+        return ret;
+    }
+
+    /// Set the pre-delay in milliseconds, up to 500ms.
+    pub fn set_pre_delay(&mut self, ms: f32) {
+        self.pre_delay.set_offset(self.pre_delay_head, ms);
+    }
+
+    /// Set the bandwidth of the input in [0, 1]. Lower values darken the
+    /// input before it enters the tank.
+    pub fn set_bandwidth(&mut self, bandwidth: f32) {
+        self.bandwidth = bandwidth.clamp(0.0, 1.0);
+    }
+
+    /// Set the diffusion of the input allpasses, in [0, 1). The first value
+    /// applies to the first two allpasses, the second to the last two.
+    pub fn set_input_diffusion(&mut self, diffusion_1: f32, diffusion_2: f32) {
+        self.input_diffusion = (diffusion_1.clamp(0.0, 0.99), diffusion_2.clamp(0.0, 0.99));
+    }
+
+    /// Set the decay of the tank in [0, 1). Higher values give longer tails.
+    pub fn set_decay(&mut self, decay: f32) {
+        self.decay = decay.clamp(0.0, 0.9999);
+    }
+
+    /// Set the diffusion of the tank allpasses, in [0, 1). The first value
+    /// applies to the modulated allpasses.
+    pub fn set_decay_diffusion(&mut self, diffusion_1: f32, diffusion_2: f32) {
+        self.decay_diffusion = (diffusion_1.clamp(0.0, 0.99), diffusion_2.clamp(0.0, 0.99));
+    }
+
+    /// Set the damping of the high frequencies in the tank, in [0, 1].
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping.clamp(0.0, 1.0);
+    }
+
+    /// Set the modulation of the tank allpasses.
+    /// # Parameters
+    /// - depth: excursion of the allpass lengths in milliseconds, up to 2ms
+    /// - rate: frequency of the modulation in hertz
+    pub fn set_modulation(&mut self, depth: f32, rate: f32) {
+        self.excursion = depth.clamp(0.0, MAX_EXCURSION_MS)/1000.0*self.sr;
+        self.lfo_rate = consts::TAU*rate/self.sr;
+    }
+
+    /// Process a stereo sample. The input is summed to mono.
+    pub fn process(&mut self, l: f32, r: f32) -> (f32, f32) {
+        // Step 1: pre-delay, bandwidth and input diffusion
+        let x = self.pre_delay.read_write(0.5*(l + r));
+        self.bandwidth_z1 += (x - self.bandwidth_z1)*self.bandwidth;
+        let mut x = self.bandwidth_z1;
+        for (i, d) in self.diffusers.iter_mut().enumerate() {
+            let fb = if i < 2 { self.input_diffusion.0 } else { self.input_diffusion.1 };
+            x = d.process(x, fb, 0.0);
+        }
+
+        // Step 2: run both halves of the tank, each fed by the other
+        self.lfo_phase = (self.lfo_phase + self.lfo_rate) % consts::TAU;
+        let excursions = [self.excursion*self.lfo_phase.sin(), self.excursion*self.lfo_phase.cos()];
+        let tank_in = [x + self.decay*self.tank_out[1], x + self.decay*self.tank_out[0]];
+        for side in 0..2 {
+            // the modulated allpasses use positive feedback
+            let y = self.tank_allpass_1[side].process(tank_in[side], -self.decay_diffusion.0, excursions[side]);
+            let y = self.tank_delay_1[side].process(y);
+            self.damping_z1[side] += (y - self.damping_z1[side])*(1.0 - self.damping);
+            let y = self.tank_allpass_2[side].process(self.damping_z1[side]*self.decay, self.decay_diffusion.1, 0.0);
+            self.tank_out[side] = self.tank_delay_2[side].process(y);
+        }
+
+        // Step 3: sum the output taps
+        let out = [0, 1].map(|out| self.taps[out].iter().map(|t| {
+            let line = match t.node {
+                TankNode::Delay1 => &self.tank_delay_1[t.side].line,
+                TankNode::AllPass2 => &self.tank_allpass_2[t.side].line,
+                TankNode::Delay2 => &self.tank_delay_2[t.side].line,
+            };
+            t.sign*line.tap(t.pos as usize)
+        }).sum::<f32>());
+        return (0.6*out[0], 0.6*out[1]);
+    }
+}
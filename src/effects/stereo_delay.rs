@@ -0,0 +1,130 @@
+use crate::effects::delay::{DelayLine, HeadId, InterpMethod, MixMethod, MusicalTime, OffsetTransition};
+
+/// Stereo delay made of two delay lines, with independent left and right
+/// times, a 2x2 feedback matrix, ping-pong routing and width control.
+///
+/// Width is applied in mid/side to the wet signal, so the mono sum of the
+/// output does not depend on it. None of the built-in routings (`set_feedback`
+/// with non-negative gains, ping-pong) inverts the polarity of a channel, so
+/// they stay mono-compatible; a custom `set_feedback_matrix` with negative
+/// gains may not.
+pub struct StereoDelay {
+    left: DelayLine,
+    right: DelayLine,
+    head_l: HeadId,
+    head_r: HeadId,
+    matrix: [[f32; 2]; 2],
+    ping_pong: bool,
+    width: f32,
+}
+
+impl StereoDelay {
+    /// Create a new stereo delay.
+    /// # Parameters
+    /// - size: maximum delay time of each channel in milliseconds
+    /// - sr: sample rate in hertz
+    /// - interp: interpolation method of both delay lines
+    pub fn new(size: f32, sr: u32, interp: InterpMethod) -> Self {
+        let mut left = DelayLine::new(size, sr, interp, MixMethod::Sum);
+        let mut right = DelayLine::new(size, sr, interp, MixMethod::Sum);
+        let head_l = left.add_head(0.0, 1.0);
+        let head_r = right.add_head(0.0, 1.0);
+        Self {
+            left: left,
+            right: right,
+            head_l: head_l,
+            head_r: head_r,
+            matrix: [[0.0; 2]; 2],
+            ping_pong: false,
+            width: 1.0,
+        }
+    }
+
+    /// Set the delay times of the left and right channels in milliseconds.
+    pub fn set_times(&mut self, left: f32, right: f32) {
+        self.left.set_offset(self.head_l, left);
+        self.right.set_offset(self.head_r, right);
+    }
+
+    /// Sync the delay times of the left and right channels to the tempo.
+    pub fn set_synced_times(&mut self, left: MusicalTime, right: MusicalTime) {
+        self.left.set_synced_offset(self.head_l, left);
+        self.right.set_synced_offset(self.head_r, right);
+    }
+
+    /// Change the tempo, updating the synced delay times.
+    pub fn set_tempo(&mut self, bpm: f32) {
+        self.left.set_tempo(bpm);
+        self.right.set_tempo(bpm);
+    }
+
+    /// Change the time signature, updating the synced delay times.
+    pub fn set_time_signature(&mut self, beats_per_bar: f32) {
+        self.left.set_time_signature(beats_per_bar);
+        self.right.set_time_signature(beats_per_bar);
+    }
+
+    /// Change how both channels react to delay time changes.
+    pub fn set_transition(&mut self, transition: OffsetTransition) {
+        self.left.set_transition(self.head_l, transition);
+        self.right.set_transition(self.head_r, transition);
+    }
+
+    /// Set the feedback of each channel into itself, and into the other
+    /// channel.
+    /// # Parameters
+    /// - feedback: gain of L -> L and R -> R
+    /// - cross: gain of L -> R and R -> L
+    pub fn set_feedback(&mut self, feedback: f32, cross: f32) {
+        self.matrix = [[feedback, cross], [cross, feedback]];
+    }
+
+    /// Set the full feedback matrix, where `matrix[i][j]` is the gain from
+    /// output `j` to input `i`, with 0 being left and 1 being right.
+    pub fn set_feedback_matrix(&mut self, matrix: [[f32; 2]; 2]) {
+        self.matrix = matrix;
+    }
+
+    /// Enable or disable ping-pong routing. In ping-pong mode the input is
+    /// summed to mono and fed to the left channel only, and the channels are
+    /// swapped in the feedback path, so echoes bounce between left and right.
+    pub fn set_ping_pong(&mut self, ping_pong: bool) {
+        self.ping_pong = ping_pong;
+    }
+
+    /// Set the stereo width of the wet signal: 0 is mono, 1 is unchanged, and
+    /// values above 1 widen the image.
+    pub fn set_width(&mut self, width: f32) {
+        self.width = width.max(0.0);
+    }
+
+    /// Access the left delay line, e.g. to set a loop processor.
+    pub fn left_mut(&mut self) -> &mut DelayLine {
+        &mut self.left
+    }
+
+    /// Access the right delay line, e.g. to set a loop processor.
+    pub fn right_mut(&mut self) -> &mut DelayLine {
+        &mut self.right
+    }
+
+    /// Process a stereo sample.
+    /// # Returns
+    /// - wet left and right outputs
+    pub fn process(&mut self, l: f32, r: f32) -> (f32, f32) {
+        let out_l = self.left.read();
+        let out_r = self.right.read();
+
+        let (in_l, in_r, fb_l, fb_r) = if self.ping_pong {
+            (0.5*(l + r), 0.0, out_r, out_l)
+        } else {
+            (l, r, out_l, out_r)
+        };
+        self.left.write(in_l + self.matrix[0][0]*fb_l + self.matrix[0][1]*fb_r);
+        self.right.write(in_r + self.matrix[1][0]*fb_l + self.matrix[1][1]*fb_r);
+
+        let mid = 0.5*(out_l + out_r);
+        let side = 0.5*(out_l - out_r)*self.width;
+        return (mid + side, mid - side);
+    }
+}
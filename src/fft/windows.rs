@@ -1,15 +1,64 @@
 use rustfft::num_complex::Complex;
 
-use std::f32::consts;
+use std::f64::consts;
 
 
+/// Window functions, as real values. Used by the functions below, and for
+/// filter design, see `effects::fir_design`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Window {
+    Rectangular,
+    Triangular,
+    Hann,
+    Blackman,
+    Nuttall,
+    FlatTop,
+}
+
+impl Window {
+    /// Value of the window at an index.
+    /// - i: index
+    /// - l_div: reciprocal of window length
+    pub fn value(self, i: usize, l_div: f64) -> f64 {
+        let t = i as f64 * l_div;
+        // sum of cosines with alternating signs
+        let cosines = |a: &[f64]| a.iter().enumerate()
+            .map(|(k, a_k)| {
+                let sign = if k.is_multiple_of(2) { 1.0 } else { -1.0 };
+                sign * a_k * (k as f64 * consts::TAU * t).cos()
+            })
+            .sum::<f64>();
+        match self {
+            Window::Rectangular => 1.0,
+            Window::Triangular => 1.0 - (2.0 * t - 1.0).abs(),
+            Window::Hann => (consts::PI * t).sin().powi(2),
+            Window::Blackman => cosines(&[0.426_59, 0.496_56, 0.076_849]),
+            Window::Nuttall => cosines(&[0.355_768, 0.487_396, 0.144_232, 0.012_604]),
+            Window::FlatTop => cosines(&[0.215_578_94, 0.416_631_58, 0.277_263_16, 0.083_578_944, 0.006_947_368]),
+        }
+    }
+
+    /// Symmetric window of `len` samples, as used for filter design. Both
+    /// ends are included, e.g. the Hann window starts and ends at 0.
+    pub fn symmetric(self, len: usize) -> Vec<f64> {
+        let l_div = 1.0 / (len.max(2) - 1) as f64;
+        return (0..len).map(|i| self.value(i, l_div)).collect();
+    }
+
+    /// Periodic window of `len` samples, as used for spectral analysis: the
+    /// window repeats every `len` samples, like the functions below.
+    pub fn periodic(self, len: usize) -> Vec<f64> {
+        let l_div = 1.0 / len.max(1) as f64;
+        return (0..len).map(|i| self.value(i, l_div)).collect();
+    }
+}
+
 /// hann window function
 /// - x: input
 /// - i: index
 /// - l_div: reciprocal of window length
 pub fn win_hann(x: Complex<f32>, i: usize, l_div: f32) -> Complex<f32> {
-    let sin_i = (i as f32 * std::f32::consts::PI * l_div).sin();
-    return x * sin_i * sin_i;
+    return x * Window::Hann.value(i, l_div as f64) as f32;
 }
 
 /// triangular window function
@@ -17,8 +66,7 @@ pub fn win_hann(x: Complex<f32>, i: usize, l_div: f32) -> Complex<f32> {
 /// - i: index
 /// - l_div: reciprocal of window length
 pub fn win_tri(x: Complex<f32>, i: usize, l_div: f32) -> Complex<f32> {
-    let tri_i = 1.0 - (2.0 * i as f32 * l_div - 1.0).abs();
-    return x * tri_i;
+    return x * Window::Triangular.value(i, l_div as f64) as f32;
 }
 
 /// blackman window function
@@ -26,12 +74,7 @@ pub fn win_tri(x: Complex<f32>, i: usize, l_div: f32) -> Complex<f32> {
 /// - i: index
 /// - l_div: reciprocal of window length
 pub fn win_black(x: Complex<f32>, i: usize, l_div: f32) -> Complex<f32> {
-    let a_0 = 0.426_59;
-    let a_1 = 0.496_56;
-    let a_2 = 0.076_849;
-    let win = a_0 - a_1 * (consts::TAU * i as f32 * l_div).cos()
-        + a_2 * (2.0 * consts::TAU * i as f32 * l_div).cos();
-    return x * win;
+    return x * Window::Blackman.value(i, l_div as f64) as f32;
 }
 
 /// nuttal window function
@@ -39,14 +82,7 @@ pub fn win_black(x: Complex<f32>, i: usize, l_div: f32) -> Complex<f32> {
 /// - i: index
 /// - l_div: reciprocal of window length
 pub fn win_nutt(x: Complex<f32>, i: usize, l_div: f32) -> Complex<f32> {
-    let a_0 = 0.355_768;
-    let a_1 = 0.487_396;
-    let a_2 = 0.144_232;
-    let a_3 = 0.012_604;
-    let win = a_0 - a_1 * (consts::TAU * i as f32 * l_div).cos()
-        + a_2 * (2.0 * consts::TAU * i as f32 * l_div).cos()
-        - a_3 * (3.0 * consts::TAU * i as f32 * l_div).cos();
-    return x * win;
+    return x * Window::Nuttall.value(i, l_div as f64) as f32;
 }
 
 /// flat top window function
@@ -54,14 +90,5 @@ pub fn win_nutt(x: Complex<f32>, i: usize, l_div: f32) -> Complex<f32> {
 /// - i: index
 /// - l_div: reciprocal of window length
 pub fn win_flat(x: Complex<f32>, i: usize, l_div: f32) -> Complex<f32> {
-    let a_0 = 0.215_578_94;
-    let a_1 = 0.416_631_58;
-    let a_2 = 0.277_263_16;
-    let a_3 = 0.083_578_944;
-    let a_4 = 0.006_947_368;
-    let win = a_0 - a_1 * (consts::TAU * i as f32 * l_div).cos()
-        + a_2 * (2.0 * consts::TAU * i as f32 * l_div).cos()
-        - a_3 * (3.0 * consts::TAU * i as f32 * l_div).cos()
-        + a_4 * (4.0 * consts::TAU * i as f32 * l_div).cos();
-    return x * win;
-}
\ No newline at end of file
+    return x * Window::FlatTop.value(i, l_div as f64) as f32;
+}
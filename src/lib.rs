@@ -1,3 +1,11 @@
+// These lints conflict with the conventions used throughout the crate.
+#![allow(
+    clippy::needless_return,
+    clippy::redundant_field_names,
+    clippy::excessive_precision,
+    clippy::new_without_default,
+)]
+
 pub mod utils;
 pub mod fft;
 pub mod vst_utils;
@@ -9,26 +17,37 @@ pub mod osc;
  */
 #[cfg(test)]
 mod tests {
-    use rand_xoshiro::rand_core::SeedableRng;
-    use rand_xoshiro::Xoshiro256Plus;
-
     use crate::utils::chaos;
     use crate::effects::delay;
+    use crate::effects::stereo_delay;
+    use crate::effects::fdn;
+    use crate::effects::freeverb;
+    use crate::effects::plate;
+    use crate::effects::filter;
+    use crate::effects::filter_design;
+    use crate::effects::fir_design;
+    use crate::effects::crossover;
+    use crate::effects::ladder;
+    use crate::effects::distortion::{Adaa1, Adaa2, Waveshaper};
+    use crate::effects::hysteresis::{Hysteresis, HysteresisSolver};
+    use crate::effects::oversampling::{Oversampler, OversamplingFactor, OversamplingFilter};
+    use crate::utils::math;
+    use crate::fft::windows;
 
     #[test]
     fn test_randf() {
-        let mut rng = Xoshiro256Plus::seed_from_u64(42635680);
+        let mut rng = chaos::Rng::new(42635680, 44100);
         for _i in 0..100000 {
-            let num = chaos::randf(&mut rng);
-            assert!(num >= 0.0 && num <= 1.0);
+            let num = rng.randf();
+            assert!((0.0..=1.0).contains(&num));
         }
     }
 
     #[test]
     fn test_delay_line_trunc_sum() {
         let mut delay_line = delay::DelayLine::new(10000.0, 2, delay::InterpMethod::Truncate, delay::MixMethod::Sum);
-        assert!(delay_line.add_head(0.0, 1.0) == 0);
-        assert!(delay_line.add_head(1000.0, 1.0) == 1);
+        delay_line.add_head(0.0, 1.0);
+        delay_line.add_head(1000.0, 1.0);
         assert!(delay_line.read_write(1.0) == 0.0);
         assert!(delay_line.read_write(1.0) == 1.0);
         assert!(delay_line.read_write(0.0) == 1.0);
@@ -37,16 +56,1231 @@ mod tests {
 
     #[test]
     fn test_delay_line_trunc_sqrt() {
-        // TODO:
+        let mut delay_line = delay::DelayLine::new(10000.0, 2, delay::InterpMethod::Truncate, delay::MixMethod::Sqrt);
+        delay_line.add_head(0.0, 1.0);
+        delay_line.add_head(1000.0, 1.0);
+        delay_line.add_head(1000.0, 1.0);
+        delay_line.add_head(1000.0, 1.0);
+        assert!(delay_line.read_write(1.0) == 0.0);
+        assert!(delay_line.read_write(0.0) == 0.5);
+        assert!(delay_line.read_write(0.0) == 0.0);
+        assert!(delay_line.read_write(0.0) == 1.5);
     }
 
     #[test]
     fn test_delay_line_round_sum() {
-        // TODO:
+        let mut delay_line = delay::DelayLine::new(10.0, 1000, delay::InterpMethod::NearestNeighbor, delay::MixMethod::Sum);
+        delay_line.add_head(2.4, 1.0);
+        delay_line.add_head(2.6, 0.5);
+        assert!(delay_line.read_write(1.0) == 0.0);
+        assert!(delay_line.read_write(0.0) == 0.0);
+        assert!(delay_line.read_write(0.0) == 0.0);
+        assert!(delay_line.read_write(0.0) == 1.0);
+        assert!(delay_line.read_write(0.0) == 0.5);
     }
 
     #[test]
     fn test_delay_line_linear_sum() {
-        // TODO:
+        let mut delay_line = delay::DelayLine::new(10.0, 1000, delay::InterpMethod::Linear, delay::MixMethod::Sum);
+        // the head gain is ignored in linear mode
+        delay_line.add_head(1.25, 2.0);
+        assert!(delay_line.read_write(1.0) == 0.0);
+        assert!(delay_line.read_write(0.0) == 0.0);
+        assert!(delay_line.read_write(0.0) == 0.75);
+        assert!(delay_line.read_write(0.0) == 0.25);
+    }
+
+    #[test]
+    fn test_delay_line_wraps_at_max_offset() {
+        // offsets past the end of the line are clamped, and reading the oldest
+        // sample with linear interpolation must not go out of bounds
+        let mut delay_line = delay::DelayLine::new(3.0, 1000, delay::InterpMethod::Linear, delay::MixMethod::Average);
+        delay_line.add_head(3.0, 1.0);
+        delay_line.add_head(50.0, 1.0);
+        for i in 0..100 {
+            let out = delay_line.read_write(1.0);
+            if i > 3 {
+                assert!(out == 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_delay_line_head_ids() {
+        let mut delay_line = delay::DelayLine::new(10.0, 1000, delay::InterpMethod::Truncate, delay::MixMethod::Sum);
+        let a = delay_line.add_head(1.0, 1.0);
+        let b = delay_line.add_head(2.0, 1.0);
+        let c = delay_line.add_head(3.0, 1.0);
+        assert!(a != b && b != c);
+
+        // removing a head must not invalidate the others
+        assert!(delay_line.remove_head(a));
+        assert!(!delay_line.remove_head(a));
+        assert!(delay_line.set_gain(c, 0.5));
+        assert!((delay_line.offset(c).unwrap() - 3.0).abs() < 1e-4);
+        assert!(delay_line.set_enabled(b, false));
+        assert!(delay_line.active_heads().collect::<Vec<_>>() == vec![c]);
+
+        delay_line.read_write(1.0);
+        for _ in 0..3 {
+            assert!(delay_line.read_write(0.0) == 0.0);
+        }
+        assert!(delay_line.read_write(0.0) == 0.5);
+    }
+
+    #[test]
+    fn test_delay_line_fractional_interp() {
+        // a slow sine delayed by a fractional offset should match the
+        // analytically delayed sine for every high-order interpolator
+        let methods = vec![
+            delay::InterpMethod::Hermite,
+            delay::InterpMethod::Lagrange,
+            delay::InterpMethod::Thiran,
+            delay::InterpMethod::Sinc,
+        ];
+        for method in methods {
+            let mut delay_line = delay::DelayLine::new(20.0, 1000, method, delay::MixMethod::Sum);
+            delay_line.add_head(10.3, 1.0);
+            let w = std::f32::consts::TAU * 0.01;
+            for n in 0..1000 {
+                let out = delay_line.read_write((w * n as f32).sin());
+                if n > 200 {
+                    let expected = (w * (n as f32 - 11.3)).sin();
+                    assert!((out - expected).abs() < 1e-2);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_delay_line_interp_edges() {
+        // reading at both ends of the line must not panic or blow up
+        let methods = vec![
+            delay::InterpMethod::Hermite,
+            delay::InterpMethod::Lagrange,
+            delay::InterpMethod::Thiran,
+            delay::InterpMethod::Sinc,
+        ];
+        for method in methods {
+            let mut delay_line = delay::DelayLine::new(5.0, 1000, method, delay::MixMethod::Sum);
+            delay_line.add_head(0.0, 1.0);
+            delay_line.add_head(0.2, 1.0);
+            delay_line.add_head(4.9, 1.0);
+            delay_line.add_head(5.0, 1.0);
+            for _ in 0..100 {
+                assert!(delay_line.read_write(1.0).abs() <= 4.5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_delay_line_interp_near_head() {
+        // offsets close to the write head must still give the right delay
+        let methods = vec![
+            delay::InterpMethod::Lagrange,
+            delay::InterpMethod::Thiran,
+            delay::InterpMethod::Sinc,
+        ];
+        for method in methods {
+            for offset in [0.3, 2.3, 6.7] {
+                let mut delay_line = delay::DelayLine::new(20.0, 1000, method, delay::MixMethod::Sum);
+                delay_line.add_head(offset, 1.0);
+                let w = std::f32::consts::TAU * 0.01;
+                let mut err = 0.0f32;
+                for n in 0..1000 {
+                    let out = delay_line.read_write((w * n as f32).sin());
+                    if n > 200 {
+                        err = err.max((out - (w * (n as f32 - offset - 1.0)).sin()).abs());
+                    }
+                }
+                assert!(err < 1e-4, "{:?} at {}: {}", method, offset, err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_delay_line_crossfade() {
+        // jumping from a loud part of the line into a silent one must fade
+        // out over the crossfade time instead of clicking
+        let mut delay_line = delay::DelayLine::new(200.0, 1000, delay::InterpMethod::Linear, delay::MixMethod::Sum);
+        let head = delay_line.add_head(1.0, 1.0);
+        assert!(delay_line.set_transition(head, delay::OffsetTransition::Crossfade(10.0)));
+        for n in 0..200 {
+            delay_line.read_write(if n < 50 { 0.0 } else { 1.0 });
+        }
+        delay_line.set_offset(head, 180.0);
+        let mut prev = delay_line.read_write(1.0);
+        for _ in 0..20 {
+            let out = delay_line.read_write(1.0);
+            assert!((out - prev).abs() <= 0.1 + 1e-6);
+            prev = out;
+        }
+        assert!(prev == 0.0);
+    }
+
+    #[test]
+    fn test_delay_line_glide() {
+        // gliding bends the pitch, but the output stays continuous
+        let mut delay_line = delay::DelayLine::new(200.0, 1000, delay::InterpMethod::Hermite, delay::MixMethod::Sum);
+        let head = delay_line.add_head(10.0, 1.0);
+        assert!(delay_line.set_transition(head, delay::OffsetTransition::Glide(50.0)));
+        let w = std::f32::consts::TAU * 0.002;
+        let mut prev = 0.0;
+        for n in 0..2000 {
+            if n == 500 {
+                delay_line.set_offset(head, 150.0);
+            }
+            let out = delay_line.read_write((w * n as f32).sin());
+            if n > 20 {
+                assert!((out - prev).abs() < 0.1);
+            }
+            prev = out;
+        }
+        assert!(delay_line.offset(head) == Some(150.0));
+    }
+
+    #[test]
+    fn test_delay_line_feedback() {
+        let mut delay_line = delay::DelayLine::new(10.0, 1000, delay::InterpMethod::Truncate, delay::MixMethod::Sum);
+        delay_line.add_head(2.0, 1.0);
+        delay_line.set_feedback(0.5);
+        let out: Vec<f32> = (0..10).map(|n| delay_line.read_write(if n == 0 { 1.0 } else { 0.0 })).collect();
+        assert!(out == vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.25]);
+
+        // per-head feedback adds up with the global feedback, and is fed
+        // through the loop processor
+        let mut delay_line = delay::DelayLine::new(10.0, 1000, delay::InterpMethod::Truncate, delay::MixMethod::Sum);
+        let head_b = delay_line.add_head(2.0, 0.0);
+        delay_line.set_head_feedback(head_b, 0.5);
+        delay_line.set_loop_processor(|x: f32| -x);
+        let out: Vec<f32> = (0..10).map(|n| delay_line.read_write(if n == 0 { 1.0 } else { 0.0 })).collect();
+        assert!(out == vec![0.0; 10]);
+        assert!(delay_line.set_gain(head_b, 1.0));
+        let out: Vec<f32> = (0..4).map(|_| delay_line.read_write(0.0)).collect();
+        assert!(out == vec![0.0, 0.0, -0.125, 0.0]);
+    }
+
+    #[test]
+    fn test_musical_time() {
+        use delay::{MusicalTime, NoteModifier};
+        assert!(MusicalTime::Note(4, NoteModifier::Straight).to_ms(120.0, 4.0) == 500.0);
+        assert!(MusicalTime::Note(8, NoteModifier::Dotted).to_ms(120.0, 4.0) == 375.0);
+        assert!((MusicalTime::Note(16, NoteModifier::Triplet).to_ms(120.0, 4.0) - 250.0/3.0).abs() < 1e-3);
+        assert!(MusicalTime::Bars(1.0).to_ms(120.0, 3.0) == 1500.0);
+        assert!(MusicalTime::Beats(2.0).to_ms(60.0, 4.0) == 2000.0);
+    }
+
+    #[test]
+    fn test_delay_line_tempo_sync() {
+        use delay::{MusicalTime, NoteModifier};
+        let mut delay_line = delay::DelayLine::new(2000.0, 1000, delay::InterpMethod::Truncate, delay::MixMethod::Sum);
+        let synced = delay_line.add_head(0.0, 1.0);
+        let free = delay_line.add_head(100.0, 1.0);
+        assert!(delay_line.set_synced_offset(synced, MusicalTime::Note(4, NoteModifier::Straight)));
+        assert!(delay_line.offset(synced) == Some(500.0));
+
+        delay_line.set_tempo(60.0);
+        assert!(delay_line.offset(synced) == Some(1000.0));
+        assert!(delay_line.offset(free) == Some(100.0));
+
+        // a manual offset breaks the sync
+        delay_line.set_offset(synced, 10.0);
+        assert!(delay_line.synced_offset(synced).is_none());
+        delay_line.set_tempo(120.0);
+        assert!(delay_line.offset(synced) == Some(10.0));
+    }
+
+    #[test]
+    fn test_stereo_delay_ping_pong() {
+        let mut stereo = stereo_delay::StereoDelay::new(100.0, 1000, delay::InterpMethod::Truncate);
+        stereo.set_times(2.0, 2.0);
+        stereo.set_feedback(0.5, 0.0);
+        stereo.set_ping_pong(true);
+        let out: Vec<(f32, f32)> = (0..10).map(|n| {
+            let x = if n == 0 { 2.0 } else { 0.0 };
+            stereo.process(x, 0.0)
+        }).collect();
+        assert!(out[3] == (1.0, 0.0));
+        assert!(out[6] == (0.0, 0.5));
+        assert!(out[9] == (0.25, 0.0));
+
+        // width only changes the side signal, the mono sum is preserved
+        stereo.set_width(0.0);
+        let mut stereo_wide = stereo_delay::StereoDelay::new(100.0, 1000, delay::InterpMethod::Truncate);
+        stereo_wide.set_times(1.0, 3.0);
+        stereo_wide.set_width(2.0);
+        for n in 0..10 {
+            let x = if n == 0 { 1.0 } else { 0.0 };
+            let (l, r) = stereo.process(x, -x);
+            let (l_w, r_w) = stereo_wide.process(x, 0.5*x);
+            assert!(l == r);
+            assert!(l_w + r_w == if n == 2 { 1.0 } else if n == 4 { 0.5 } else { 0.0 });
+        }
+    }
+
+    #[test]
+    fn test_fdn_reverb_decay() {
+        // the impulse response should fall by roughly 60dB after rt60 seconds,
+        // for every size and mixing matrix
+        let sizes = [fdn::FdnSize::Four, fdn::FdnSize::Eight, fdn::FdnSize::Sixteen];
+        let matrices = [fdn::MixMatrix::Householder, fdn::MixMatrix::Hadamard, fdn::MixMatrix::RandomOrthogonal(42)];
+        for size in sizes.iter() {
+            for matrix in matrices.iter() {
+                let mut reverb = fdn::FdnReverb::new(8000, *size, *matrix);
+                reverb.set_decay(1.0, 1.0, 1.0);
+                reverb.set_modulation(0.0, 0.0);
+                let response: Vec<f32> = (0..16000)
+                    .map(|n| reverb.process_mono(if n == 0 { 1.0 } else { 0.0 }))
+                    .collect();
+                let rms = |from: usize| (response[from..from + 1600].iter().map(|x| x*x).sum::<f32>() / 1600.0).sqrt();
+                let drop_db = 20.0*(rms(1600) / rms(9600)).log10();
+                assert!(drop_db > 50.0 && drop_db < 70.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fdn_reverb_band_decay() {
+        // with a short high band decay, a high tone dies out faster than a
+        // low one
+        let sr = 8000;
+        let tail_energy = |freq: f32| {
+            let mut reverb = fdn::FdnReverb::new(sr, fdn::FdnSize::Eight, fdn::MixMatrix::Hadamard);
+            reverb.set_decay(2.0, 2.0, 0.2);
+            reverb.set_crossover(200.0, 1000.0);
+            let w = std::f32::consts::TAU * freq / sr as f32;
+            (0..8000).map(|n| {
+                let x = if n < 800 { (w * n as f32).sin() } else { 0.0 };
+                let (l, r) = reverb.process(x, x);
+                if n > 4000 { l*l + r*r } else { 0.0 }
+            }).sum::<f32>()
+        };
+        assert!(tail_energy(100.0) > 100.0*tail_energy(3000.0));
+    }
+
+    #[test]
+    fn test_lp_comb() {
+        let mut comb = filter::LpComb::with_len(3, 0.5, 0.0);
+        let out: Vec<f32> = (0..10).map(|n| comb.filter(if n == 0 { 1.0 } else { 0.0 })).collect();
+        assert!(out == vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.25]);
+
+        // damping smears the echoes
+        let mut comb = filter::LpComb::with_len(3, 0.5, 0.5);
+        let out: Vec<f32> = (0..8).map(|n| comb.filter(if n == 0 { 1.0 } else { 0.0 })).collect();
+        assert!(out == vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.25, 0.125]);
+    }
+
+    #[test]
+    fn test_freeverb_reference() {
+        // with the default parameters, the first echo of each channel arrives
+        // after the shortest comb, scaled by the fixed input gain
+        let mut reverb = freeverb::Freeverb::new(44100);
+        let out: Vec<(f32, f32)> = (0..1200).map(|n| {
+            let x = if n == 0 { 1.0 } else { 0.0 };
+            reverb.process(x, x)
+        }).collect();
+        assert!(out[..1116].iter().all(|(l, r)| *l == 0.0 && *r == 0.0));
+        assert!((out[1116].0 - 0.03).abs() < 1e-7);
+        assert!(out[1116..1139].iter().all(|(_, r)| *r == 0.0));
+        assert!((out[1139].1 - 0.03).abs() < 1e-7);
+    }
+
+    #[test]
+    fn test_freeverb_freeze() {
+        let mut reverb = freeverb::Freeverb::new(22050);
+        for n in 0..4000 {
+            let x = if n < 100 { 1.0 } else { 0.0 };
+            reverb.process(x, x);
+        }
+        reverb.set_freeze(true);
+        let energy = |reverb: &mut freeverb::Freeverb| (0..40000).map(|_| {
+            let (l, r) = reverb.process(1.0, 1.0);
+            l*l + r*r
+        }).sum::<f32>();
+        let first = energy(&mut reverb);
+        let second = energy(&mut reverb);
+        assert!(first > 0.0 && (first - second).abs() / first < 0.1);
+
+        // mono width
+        let mut reverb = freeverb::Freeverb::new(22050);
+        reverb.set_width(0.0);
+        for n in 0..2000 {
+            let (l, r) = reverb.process(if n == 0 { 1.0 } else { 0.0 }, 0.0);
+            assert!((l - r).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_plate_reverb_sample_rates() {
+        // the tail should decay at the same rate regardless of sample rate
+        let decay_time = |sr: u32| {
+            let mut reverb = plate::PlateReverb::new(sr);
+            reverb.set_decay(0.7);
+            let block = sr as usize / 20;
+            let mut blocks = Vec::new();
+            for b in 0..60 {
+                let energy = (0..block).map(|n| {
+                    let x = if b == 0 && n == 0 { 1.0 } else { 0.0 };
+                    let (l, r) = reverb.process(x, x);
+                    l*l + r*r
+                }).sum::<f32>();
+                blocks.push(energy);
+            }
+            // blocks until the energy falls 40dB below the loudest block
+            let peak = blocks.iter().cloned().fold(0.0, f32::max);
+            blocks.iter().rposition(|e| *e > peak*1e-4).unwrap()
+        };
+        let reference = decay_time(44100) as i32;
+        assert!(reference > 10);
+        assert!((decay_time(48000) as i32 - reference).abs() <= 2);
+        assert!((decay_time(96000) as i32 - reference).abs() <= 2);
+    }
+
+    #[test]
+    fn test_plate_reverb_pre_delay() {
+        let mut reverb = plate::PlateReverb::new(48000);
+        reverb.set_pre_delay(100.0);
+        for n in 0..4800 {
+            let (l, r) = reverb.process(if n == 0 { 1.0 } else { 0.0 }, 0.0);
+            assert!(l == 0.0 && r == 0.0);
+        }
+        let tail = (0..48000).map(|_| reverb.process(0.0, 0.0).0.abs()).fold(0.0, f32::max);
+        assert!(tail > 0.0 && tail < 1.0);
+    }
+
+    #[test]
+    fn test_plate_reverb_taps() {
+        // every output tap must read inside the node it taps, at any sample
+        // rate
+        for sr in [22050.0, 29761.0, 44100.0, 48000.0, 96000.0, 192000.0] {
+            let scale = sr / plate::REFERENCE_SR;
+            for tap in plate::TAPS.iter().flatten() {
+                assert!((tap.pos*scale).floor() < (tap.node.len(tap.side)*scale).floor(), "{:?} at {}", tap, sr);
+            }
+        }
+    }
+
+    /// Steady state gain of a filter for a sine at the given frequency.
+    fn sine_gain<F: FnMut(f32) -> f32>(mut filter: F, freq: f32, sr: f32) -> f32 {
+        let w = std::f32::consts::TAU * freq / sr;
+        let n_settle = sr as usize;
+        let n_measure = sr as usize / 2;
+        let mut peak = 0.0f32;
+        for n in 0..n_settle + n_measure {
+            let y = filter((w * n as f32).sin());
+            if n >= n_settle {
+                peak = peak.max(y.abs());
+            }
+        }
+        return peak;
+    }
+
+    fn db(x: f32) -> f32 {
+        20.0 * x.log10()
+    }
+
+    #[test]
+    fn test_svf_responses() {
+        let sr = 48000.0;
+        let mut svf = filter::Svf::new();
+        assert!((sine_gain(|x| svf.filter(x, 1000.0, 0.0, sr).0, 20.0, sr) - 1.0).abs() < 1e-2);
+        let mut svf = filter::Svf::new();
+        assert!(sine_gain(|x| svf.filter(x, 1000.0, 0.0, sr).0, 10000.0, sr) < 0.02);
+        let mut svf = filter::Svf::new();
+        assert!((sine_gain(|x| svf.filter(x, 1000.0, 0.0, sr).1, 20000.0, sr) - 1.0).abs() < 1e-2);
+        let mut svf = filter::Svf::new();
+        assert!(sine_gain(|x| svf.filter(x, 1000.0, 0.0, sr).2, 1000.0, sr) < 1e-2);
+        let mut svf = filter::Svf::new();
+        assert!((sine_gain(|x| svf.filter_all(x, 1000.0, 0.7, sr).allpass, 3000.0, sr) - 1.0).abs() < 1e-2);
+
+        // lowpass at the cutoff is at -3dB for a Q of 1/sqrt(2)
+        let res = 1.0 - 0.5*std::f32::consts::SQRT_2;
+        let mut svf = filter::Svf::new();
+        assert!((db(sine_gain(|x| svf.filter(x, 5000.0, res, sr).0, 5000.0, sr)) + 3.01).abs() < 0.1);
+
+        let mut svf = filter::Svf::new();
+        assert!((db(sine_gain(|x| svf.bell(x, 2000.0, 0.5, 6.0, sr), 2000.0, sr)) - 6.0).abs() < 0.1);
+        let mut svf = filter::Svf::new();
+        assert!((db(sine_gain(|x| svf.low_shelf(x, 2000.0, 0.3, -9.0, sr), 20.0, sr)) + 9.0).abs() < 0.1);
+        let mut svf = filter::Svf::new();
+        assert!((db(sine_gain(|x| svf.high_shelf(x, 2000.0, 0.3, 4.0, sr), 20000.0, sr)) - 4.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_svf_stability() {
+        // cutoff at and above Nyquist, maximum resonance, fast modulation
+        let sr = 44100.0;
+        let mut svf = filter::Svf::new();
+        let mut rng = chaos::Rng::new(1234, 44100);
+        for n in 0..100000 {
+            let cutoff = if n % 2 == 0 { 30000.0 } else { 20.0 + 10000.0*rng.randf() };
+            let (lp, hp, notch, bp) = svf.filter(rng.randf() - 0.5, cutoff, 0.999, sr);
+            assert!(lp.abs() < 1e3 && hp.abs() < 1e3 && notch.abs() < 1e3 && bp.abs() < 1e3);
+        }
+    }
+
+    #[test]
+    fn test_svf_cached_coefs() {
+        let sr = 44100.0;
+        let mut a = filter::Svf::new();
+        let mut b = filter::Svf::new();
+        let mut c = filter::Svf::new();
+        let mut d = filter::Svf::new();
+        b.set_params(3000.0, 0.6, sr);
+        let shared = filter::SvfCoefs::new(3000.0, 0.6, sr);
+        d.set_params_fast(3000.0, 0.6, sr);
+        for n in 0..1000 {
+            let x = (n as f32 * 0.1).sin();
+            let out_a = a.filter_all(x, 3000.0, 0.6, sr);
+            assert!(out_a == b.process(x));
+            assert!(out_a == c.process_with(x, &shared));
+            assert!((out_a.lp - d.process(x).lp).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_fast_tan() {
+        for i in 0..1000 {
+            let x = 0.45 * std::f32::consts::PI * i as f32 / 1000.0;
+            assert!((math::fast_tan(x) - x.tan()).abs() <= 1e-6 * x.tan().max(1.0));
+        }
+        for i in -1000..1000 {
+            let x = 10.0 * i as f32 / 1000.0;
+            let tol = if x.abs() < 3.5 { 1e-4 } else { 1e-2 };
+            assert!((math::fast_tanh(x) - x.tanh()).abs() <= tol);
+        }
+    }
+
+    #[test]
+    fn test_biquad_responses() {
+        use filter::{Biquad, BiquadForm, BiquadType};
+        let sr = 48000.0;
+        let forms = [BiquadForm::DirectForm1, BiquadForm::TransposedDirectForm2];
+        for form in forms.iter() {
+            let cases = [
+                (BiquadType::Lowpass, 100.0, 0.0),
+                (BiquadType::Lowpass, 1000.0, -3.01),
+                (BiquadType::Highpass, 1000.0, -3.01),
+                (BiquadType::Highpass, 20000.0, 0.0),
+                (BiquadType::Bandpass, 1000.0, 0.0),
+                (BiquadType::Allpass, 5000.0, 0.0),
+                (BiquadType::Peaking(6.0), 1000.0, 6.0),
+                (BiquadType::LowShelf(-12.0), 20.0, -12.0),
+                (BiquadType::HighShelf(3.0), 20000.0, 3.0),
+            ];
+            for (kind, freq, expected) in cases.iter() {
+                let mut bq = Biquad::<f32>::new(*form);
+                bq.set_params(*kind, 1000.0, std::f32::consts::FRAC_1_SQRT_2, sr);
+                assert!((db(sine_gain(|x| bq.filter(x), *freq, sr)) - expected).abs() < 0.1);
+            }
+            let mut bq = Biquad::<f32>::new(*form);
+            bq.set_params(BiquadType::Notch, 1000.0, 0.7, sr);
+            assert!(sine_gain(|x| bq.filter(x), 1000.0, sr) < 1e-2);
+        }
+
+        // double precision keeps a very low cutoff accurate
+        let mut bq = Biquad::<f64>::new(BiquadForm::DirectForm1);
+        bq.set_params(BiquadType::Lowpass, 10.0, 0.5, 96000.0);
+        let mut y = 0.0;
+        for _ in 0..200000 {
+            y = bq.filter(1.0);
+        }
+        assert!((y - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_biquad_smoothing() {
+        use filter::{Biquad, BiquadForm, BiquadType};
+        let sr = 48000.0;
+        let mut smooth = Biquad::<f32>::new(BiquadForm::TransposedDirectForm2);
+        smooth.set_smoothing(5.0, sr);
+        smooth.set_params(BiquadType::Peaking(12.0), 1000.0, 1.0, sr);
+        smooth.reset();
+        let target = filter::BiquadCoefs::new(BiquadType::Peaking(-12.0), 4000.0, 1.0, sr);
+        smooth.set_coefs(target);
+        // coefficients move gradually, and reach the target eventually
+        smooth.filter(0.0);
+        assert!(smooth.coefs() != target);
+        for _ in 0..239 {
+            smooth.filter(0.0);
+        }
+        assert!(smooth.coefs() == target);
+    }
+
+    /// Gain in decibels of cascaded sections at the given frequency.
+    fn sections_db(sections: &[filter::BiquadCoefs<f64>], freq: f64, sr: f64) -> f64 {
+        use rustfft::num_complex::Complex;
+        let z = Complex::new(0.0, -std::f64::consts::TAU * freq / sr).exp();
+        let h: Complex<f64> = sections.iter().map(|c| {
+            (z*(z*c.b2 + c.b1) + c.b0) / (z*(z*c.a2 + c.a1) + 1.0)
+        }).product();
+        20.0 * h.norm().log10()
+    }
+
+    #[test]
+    fn test_filter_design_responses() {
+        use filter_design::{design, Band, Prototype};
+        let sr = 48000.0;
+        for order in 1..=9 {
+            let bw = design(Prototype::Butterworth, Band::Lowpass, order, 1000.0, sr);
+            assert!(bw.len() == order.div_ceil(2));
+            assert!(sections_db(&bw, 0.0, sr).abs() < 1e-9);
+            assert!((sections_db(&bw, 1000.0, sr) + 3.01).abs() < 0.01);
+
+            let bw = design(Prototype::Butterworth, Band::Highpass, order, 1000.0, sr);
+            assert!(sections_db(&bw, 23999.0, sr).abs() < 1e-3);
+            assert!((sections_db(&bw, 1000.0, sr) + 3.01).abs() < 0.01);
+
+            let bessel = design(Prototype::Bessel, Band::Lowpass, order, 1000.0, sr);
+            assert!((sections_db(&bessel, 1000.0, sr) + 3.01).abs() < 0.05);
+
+            let cheby = design(Prototype::Chebyshev1 { ripple: 1.0 }, Band::Lowpass, order, 1000.0, sr);
+            for i in 0..100 {
+                let gain = sections_db(&cheby, 10.0 * i as f64, sr);
+                assert!(gain < 1e-6 && gain > -1.0 - 1e-6);
+            }
+            assert!((sections_db(&cheby, 1000.0, sr) + 1.0).abs() < 0.01);
+
+            let cheby = design(Prototype::Chebyshev2 { attenuation: 50.0 }, Band::Highpass, order, 1000.0, sr);
+            for i in 0..100 {
+                assert!(sections_db(&cheby, 10.0 * i as f64, sr) < -50.0 + 1e-6);
+            }
+            assert!(sections_db(&cheby, 23999.0, sr).abs() < 1e-3);
+
+            if order >= 3 {
+                let ellip = design(Prototype::Elliptic { ripple: 0.5, attenuation: 60.0 }, Band::Lowpass, order, 1000.0, sr);
+                for i in 0..100 {
+                    let gain = sections_db(&ellip, 10.0 * i as f64, sr);
+                    assert!(gain < 1e-6 && gain > -0.5 - 1e-6);
+                }
+                for i in 0..100 {
+                    assert!(sections_db(&ellip, 10000.0 + 100.0 * i as f64, sr) < -60.0 + 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_filter_design_steepness() {
+        use filter_design::{design, Band, Prototype};
+        let sr = 96000.0;
+        let ellip = design(Prototype::Elliptic { ripple: 0.1, attenuation: 100.0 }, Band::Lowpass, 12, 20000.0, sr);
+        assert!(sections_db(&ellip, 19000.0, sr) > -0.1 - 1e-6);
+        assert!(sections_db(&ellip, 24000.0, sr) < -100.0 + 1e-3);
+
+        // the runtime cascade matches the designed response
+        let mut cascade = filter_design::Cascade::<f64>::design(Prototype::Elliptic { ripple: 0.1, attenuation: 100.0 }, Band::Lowpass, 12, 20000.0, sr);
+        // (the sine is generated in f64, as f32 phase noise would exceed -100dB)
+        let w = std::f64::consts::TAU * 30000.0 / sr;
+        let mut peak = 0.0f64;
+        for n in 0..2 * sr as usize {
+            let y = cascade.filter((w * n as f64).sin());
+            if n >= sr as usize {
+                peak = peak.max(y.abs());
+            }
+        }
+        assert!(peak < 1e-5);
+    }
+
+    #[test]
+    fn test_crossover_sum() {
+        use crossover::{Crossover, CrossoverOrder};
+        let sr = 48000.0;
+        for order in [CrossoverOrder::Lr2, CrossoverOrder::Lr4, CrossoverOrder::Lr8] {
+            // both bands are at -6dB at the crossover frequency
+            let mut xover = Crossover::<f32>::new(order, 1000.0, sr as f64);
+            assert!((db(sine_gain(|x| xover.process(x).0, 1000.0, sr)) + 6.02).abs() < 0.05);
+            let mut xover = Crossover::<f32>::new(order, 1000.0, sr as f64);
+            assert!((db(sine_gain(|x| xover.process(x).1, 1000.0, sr)) + 6.02).abs() < 0.05);
+
+            // and sum to a flat response
+            for freq in [50.0, 700.0, 1000.0, 1400.0, 10000.0] {
+                let mut xover = Crossover::<f32>::new(order, 1000.0, sr as f64);
+                let gain = sine_gain(|x| { let (l, h) = xover.process(x); l + h }, freq, sr);
+                assert!(db(gain).abs() < 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn test_multiband_splitter() {
+        use crossover::{CrossoverOrder, MultibandSplitter};
+        let sr = 48000.0;
+        let freqs = [200.0, 1000.0, 5000.0];
+        for order in [CrossoverOrder::Lr2, CrossoverOrder::Lr4, CrossoverOrder::Lr8] {
+            // (frequencies are chosen so that the sampled peaks of the sine
+            // are close to its true peak)
+            for freq in [31.0, 210.0, 610.0, 1010.0, 3030.0, 11030.0] {
+                let mut splitter = MultibandSplitter::<f32>::new(order, &freqs, sr as f64);
+                assert!(splitter.bands() == 4);
+                let mut bands = [0.0f32; 4];
+                let gain = sine_gain(|x| { splitter.process(x, &mut bands); bands.iter().sum() }, freq, sr);
+                assert!(db(gain).abs() < 0.05);
+            }
+        }
+
+        // a sine in the middle of a band ends up mostly in that band
+        let mut splitter = MultibandSplitter::<f32>::new(CrossoverOrder::Lr4, &freqs, sr as f64);
+        assert!(splitter.set_freq(1, 1200.0));
+        assert!(!splitter.set_freq(3, 1200.0));
+        let mut bands = [0.0f32; 4];
+        assert!(db(sine_gain(|x| { splitter.process(x, &mut bands); bands[2] }, 2500.0, sr)).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_ladder_responses() {
+        use ladder::{Ladder, LadderModel};
+        let sr = 48000.0;
+        for model in [LadderModel::Huovilainen, LadderModel::Zdf] {
+            // small signals stay in the linear region of the saturation
+            let gain = |freq: f32, res: f32, comp: f32, lp4: bool| {
+                let mut ladder = Ladder::new(model);
+                ladder.set_params(1000.0, res, sr);
+                ladder.set_compensation(comp);
+                let out = sine_gain(|x| {
+                    let y = ladder.process(0.01 * x);
+                    if lp4 { y.lp4 } else { y.lp2 }
+                }, freq, sr);
+                db(out / 0.01)
+            };
+            assert!(gain(21.0, 0.0, 0.0, true).abs() < 0.1);
+            assert!((gain(1010.0, 0.0, 0.0, true) + 12.0).abs() < 1.5);
+            // 24dB/octave and 12dB/octave slopes, about 23dB and 11dB over
+            // this octave for the analog filter
+            let slope = gain(3010.0, 0.0, 0.0, true) - gain(6020.0, 0.0, 0.0, true);
+            assert!(slope > 20.0 && slope < 25.0);
+            let slope = gain(3010.0, 0.0, 0.0, false) - gain(6020.0, 0.0, 0.0, false);
+            assert!(slope > 10.0 && slope < 13.0);
+
+            // resonance lowers the passband unless compensated, and peaks
+            // at the cutoff
+            assert!((gain(21.0, 0.9, 0.0, true) + 20.0 * 4.6f32.log10()).abs() < 0.2);
+            assert!(gain(21.0, 0.9, 1.0, true).abs() < 0.2);
+            assert!(gain(1010.0, 0.9, 1.0, true) > 6.0);
+        }
+    }
+
+    #[test]
+    fn test_ladder_self_oscillation() {
+        use ladder::{Ladder, LadderModel};
+        let sr = 48000.0;
+        for model in [LadderModel::Huovilainen, LadderModel::Zdf] {
+            let mut ladder = Ladder::new(model);
+            ladder.set_params(500.0, 1.1, sr);
+            ladder.set_drive(2.0);
+            ladder.process(1.0);
+            let mut crossings = 0;
+            let mut prev = 0.0;
+            let mut peak = 0.0f32;
+            for n in 0..2 * sr as usize {
+                let y = ladder.process(0.0).lp4;
+                if n >= sr as usize {
+                    if prev < 0.0 && y >= 0.0 {
+                        crossings += 1;
+                    }
+                    peak = peak.max(y.abs());
+                }
+                prev = y;
+            }
+            // a sustained, bounded sine at about the cutoff
+            assert!(peak > 0.02 && peak < 1.0);
+            assert!((crossings as f32 - 500.0).abs() < 25.0);
+        }
+    }
+
+    #[test]
+    fn test_nonlinear_svf() {
+        use filter::{NonlinearSvf, Svf, SvfShaper};
+        let sr = 48000.0;
+        let shapers = [SvfShaper::Tanh, SvfShaper::VarClip(0.0), SvfShaper::VarClip(0.9)];
+        for shaper in shapers.iter() {
+            // small signals are filtered like the linear filter
+            let mut svf = Svf::new();
+            let mut nl_svf = NonlinearSvf::new(*shaper);
+            svf.set_params(2000.0, 0.5, sr);
+            nl_svf.set_params(2000.0, 0.5, sr);
+            let mut rng = chaos::Rng::new(1234, 48000);
+            for _ in 0..10000 {
+                let x = 1e-4 * (rng.randf() - 0.5);
+                let (y, nl_y) = (svf.process(x), nl_svf.process(x));
+                assert!((y.lp - nl_y.lp).abs() < 1e-6 && (y.bp - nl_y.bp).abs() < 1e-6);
+            }
+
+            // past full resonance it self-oscillates with a bounded amplitude
+            let mut nl_svf = NonlinearSvf::new(*shaper);
+            nl_svf.set_params(1000.0, 1.05, sr);
+            nl_svf.set_drive(4.0);
+            let (mut peak_1, mut peak_2) = (0.0f32, 0.0f32);
+            for n in 0..2 * sr as usize {
+                let y = nl_svf.process(if n < 100 { 10.0 } else { 0.0 }).lp.abs();
+                if n >= sr as usize / 2 && n < sr as usize {
+                    peak_1 = peak_1.max(y);
+                } else if n >= 3 * sr as usize / 2 {
+                    peak_2 = peak_2.max(y);
+                }
+            }
+            assert!(peak_1 > 1e-4 && peak_1 < 1.0);
+            assert!((peak_1 - peak_2).abs() < 0.01 * peak_1);
+        }
+    }
+
+    #[test]
+    fn test_block_dc() {
+        use filter::BlockDC;
+        // the presets have the same response at any sample rate, and match
+        // the original fixed poles at 44.1kHz
+        for (sr, pole) in [(44100.0, 0.995), (96000.0, 0.995f32.powf(44100.0 / 96000.0))] {
+            let mut dc = BlockDC::weak(sr);
+            let mut reference = (0.0, 0.0);
+            for n in 0..sr as usize {
+                let x = 1.0 + (0.1 * n as f32).sin();
+                let y = dc.filter(x);
+                reference.1 = x - reference.0 + pole * reference.1;
+                reference.0 = x;
+                assert!((y - reference.1).abs() < 1e-3);
+            }
+        }
+        // the deprecated methods keep the original fixed poles, and leave the
+        // order of the filter alone
+        #[allow(deprecated)]
+        {
+            let mut old = BlockDC::new();
+            let mut fourth = BlockDC::with_order(20.0, 4, 48000.0);
+            let (mut x_z1, mut y_z1) = (0.0, 0.0);
+            for n in 0..100 {
+                let x = (0.3 * n as f32).sin();
+                y_z1 = x - x_z1 + 0.9 * y_z1;
+                x_z1 = x;
+                assert_eq!(old.filter_medium(x), y_z1);
+                fourth.filter_medium(x);
+            }
+            assert!((db(sine_gain(|x| fourth.filter(x), 1000.0, 48000.0))).abs() < 0.01);
+            assert!(db(sine_gain(|x| fourth.filter(x), 5.0, 48000.0)) < -40.0);
+        }
+        let mut dc = BlockDC::weak(44100.0);
+        let weak_44 = db(sine_gain(|x| dc.filter(x), 20.0, 44100.0));
+        let mut dc = BlockDC::weak(96000.0);
+        assert!((db(sine_gain(|x| dc.filter(x), 20.0, 96000.0)) - weak_44).abs() < 0.1);
+
+        // higher orders remove more sub-sonic content, and remove DC
+        let sr = 48000.0;
+        let mut first = BlockDC::from_cutoff(20.0, sr);
+        let mut fourth = BlockDC::with_order(20.0, 4, sr);
+        assert!(db(sine_gain(|x| fourth.filter(x), 5.0, sr)) < db(sine_gain(|x| first.filter(x), 5.0, sr)) - 20.0);
+        let mut fourth = BlockDC::with_order(20.0, 4, sr);
+        assert!((db(sine_gain(|x| fourth.filter(x), 1000.0, sr))).abs() < 0.01);
+        let mut y = 1.0;
+        for _ in 0..sr as usize {
+            y = fourth.filter(1.0);
+        }
+        assert!(y.abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_fractional_allpass() {
+        use filter::AllPass;
+        let sr = 48000;
+        // the delay is not rounded: the impulse response of the allpass is
+        // centered between samples 10 and 11
+        let mut ap = AllPass::new(sr as f32 / 10.5, 0.5, sr);
+        let mut y = [0.0f32; 20];
+        for (n, y) in y.iter_mut().enumerate() {
+            *y = ap.filter(if n == 0 { 1.0 } else { 0.0 });
+        }
+        assert!(y[0] == 0.5);
+        assert!(y[1..10].iter().all(|y| y.abs() < 0.1));
+        assert!((y[10] - y[11]).abs() < 0.05 && y[10] > 0.3);
+
+        // the magnitude response stays flat, also under modulation
+        for freq in [101.0, 1010.0, 5030.0] {
+            let mut ap = AllPass::with_max_delay(30.0, 60.0, 0.7);
+            assert!(db(sine_gain(|x| ap.filter(x), freq, sr as f32)).abs() < 0.5);
+        }
+        let mut ap = AllPass::with_max_delay(30.0, 60.0, 0.7);
+        let mut phase = 0.0f32;
+        for n in 0..sr {
+            phase += 0.5 / sr as f32;
+            ap.set_delay(45.0 + 10.0 * (std::f32::consts::TAU * phase).sin());
+            assert!(ap.filter((0.01 * n as f32).sin()).abs() < 4.0);
+        }
+    }
+
+    #[test]
+    fn test_first_order_allpass() {
+        use filter::{AllPassChain, FirstOrderAllPass};
+        let sr = 48000.0;
+        // the output lags by a quarter period at the break frequency
+        let mut ap = FirstOrderAllPass::new(1000.0, sr);
+        let w = std::f32::consts::TAU * 1000.0 / sr;
+        for n in 0..10000 {
+            let y = ap.filter((w * n as f32).sin());
+            if n > 1000 {
+                assert!((y + (w * n as f32).cos()).abs() < 1e-3);
+            }
+        }
+        for freq in [21.0, 1010.0, 15030.0] {
+            let mut ap = FirstOrderAllPass::new(1000.0, sr);
+            assert!(db(sine_gain(|x| ap.filter(x), freq, sr)).abs() < 0.05);
+        }
+
+        // two stages mixed with the dry signal notch the break frequency
+        let mut chain = AllPassChain::new(2, 1000.0, sr);
+        assert!(db(sine_gain(|x| 0.5 * (x + chain.filter(x)), 1000.0, sr)) < -40.0);
+        let mut chain = AllPassChain::new(2, 1000.0, sr);
+        chain.set_freqs(&[2000.0, 500.0], sr);
+        assert!(db(sine_gain(|x| 0.5 * (x + chain.filter(x)), 1000.0, sr)) < -40.0);
+    }
+
+    #[test]
+    fn test_frequency_response() {
+        use filter::{AllPass, BiquadType, BlockDC, FirstOrderAllPass, FrequencyResponse, Svf, SvfOutput};
+        let sr = 48000.0;
+
+        // the evaluated response matches the measured one
+        let mut svf = Svf::new();
+        svf.set_params(1000.0, 0.7, sr as f32);
+        for freq in [101.0, 1010.0, 2030.0] {
+            let expected = svf.response(SvfOutput::Lowpass).magnitude_db(freq, sr);
+            let mut svf = Svf::new();
+            svf.set_params(1000.0, 0.7, sr as f32);
+            let measured = db(sine_gain(|x| svf.process(x).lp, freq as f32, sr as f32));
+            assert!((expected - measured as f64).abs() < 0.05);
+        }
+        assert!(svf.response(SvfOutput::Allpass).magnitude_db(3000.0, sr).abs() < 1e-9);
+        assert!(svf.response(SvfOutput::Notch).magnitude_db(1000.0, sr) < -100.0);
+        assert!((svf.response(SvfOutput::Bandpass).phase(1000.0, sr)).abs() < 1e-6);
+
+        let bell = filter::BiquadCoefs::<f32>::new(BiquadType::Peaking(6.0), 2000.0, 1.0, sr as f32);
+        assert!((bell.magnitude_db(2000.0, sr) - 6.0).abs() < 1e-4);
+        let cascade = filter_design::Cascade::<f64>::design(filter_design::Prototype::Bessel, filter_design::Band::Lowpass, 5, 1000.0, sr);
+        let sections = filter_design::design(filter_design::Prototype::Bessel, filter_design::Band::Lowpass, 5, 1000.0, sr);
+        assert!((cascade.magnitude_db(1500.0, sr) - sections_db(&sections, 1500.0, sr)).abs() < 1e-9);
+
+        let dc = BlockDC::with_order(20.0, 3, sr as f32);
+        assert!(dc.magnitude_db(0.0, sr) < -200.0);
+        assert!(dc.magnitude_db(1000.0, sr).abs() < 0.01);
+
+        // group delays of allpasses at DC
+        let ap = AllPass::with_max_delay(10.5, 10.5, 0.5);
+        assert!(ap.magnitude_db(1234.0, sr).abs() < 1e-9);
+        assert!((ap.group_delay(0.0, sr) - 10.5 * 0.5 / 1.5).abs() < 1e-4);
+        let ap = FirstOrderAllPass::new(1000.0, sr as f32);
+        assert!((ap.phase(1000.0, sr) + std::f64::consts::FRAC_PI_2).abs() < 1e-4);
+        let g = (std::f64::consts::PI * 1000.0 / sr).tan();
+        assert!((ap.group_delay(0.0, sr) - 1.0 / g).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_integrator_diff() {
+        use filter::{Diff, FrequencyResponse, IntegrationMethod, Integrator};
+        // forward Euler drifts on DC, the leaky integrator settles at the
+        // time constant times the input
+        let mut euler = Integrator::new();
+        let mut leaky = Integrator::with_method(IntegrationMethod::Leaky(100.0));
+        let (mut y_euler, mut y_leaky) = (0.0, 0.0);
+        for _ in 0..10000 {
+            y_euler = euler.filter(0.5);
+            y_leaky = leaky.filter(0.5);
+        }
+        assert!(y_euler == 5000.0);
+        assert!((y_leaky - 50.0).abs() < 1e-3);
+
+        // the first difference is returned, and the trapezoidal integrator
+        // and differentiator are exact inverses
+        let mut diff = Diff::new();
+        assert!(diff.filter(1.0) == 1.0 && diff.filter(3.0) == 2.0);
+        let mut integrator = Integrator::with_method(IntegrationMethod::Trapezoidal);
+        let mut diff = Diff::with_method(IntegrationMethod::Trapezoidal);
+        let mut rng = chaos::Rng::new(9876, 48000);
+        for _ in 0..1000 {
+            let x = rng.randf() - 0.5;
+            let y = diff.diff(integrator.integrate(x, 0.01), 0.01);
+            assert!((x - y).abs() < 1e-3);
+        }
+
+        // the derivative of a sine is a cosine, and the leaky differentiator
+        // removes DC
+        let sr = 48000.0;
+        let w = std::f32::consts::TAU * 100.0 / sr;
+        let mut diff = Diff::with_method(IntegrationMethod::Leaky(1.0 / (2.0 * sr)));
+        for n in 0..10000 {
+            let y = diff.diff(1.0 + (w * n as f32).sin(), 1.0 / sr);
+            if n > 100 {
+                let expected = std::f32::consts::TAU * 100.0 * (w * n as f32).cos();
+                assert!((y - expected).abs() < 1e-2 * std::f32::consts::TAU * 100.0);
+            }
+        }
+        assert!(diff.response(0.0, sr as f64).norm() == 0.0);
+        let trapezoidal = Integrator::with_method(IntegrationMethod::Trapezoidal);
+        let w = std::f64::consts::TAU * 1000.0 / sr as f64;
+        assert!((trapezoidal.response(1000.0, sr as f64).norm() - 0.5 / (w / 2.0).tan()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_windows() {
+        use rustfft::num_complex::Complex;
+        use windows::Window;
+        let hann = Window::Hann.symmetric(5);
+        let expected = [0.0, 0.5, 1.0, 0.5, 0.0];
+        assert!(hann.iter().zip(expected.iter()).all(|(a, b)| (a - b).abs() < 1e-12));
+        // the complex versions apply the same periodic windows
+        let one = Complex::new(1.0f32, 0.0);
+        let blackman = Window::Blackman.periodic(64);
+        let flat_top = Window::FlatTop.periodic(64);
+        for i in 0..64 {
+            assert!((windows::win_black(one, i, 1.0 / 64.0).re - blackman[i] as f32).abs() < 1e-6);
+            assert!((windows::win_flat(one, i, 1.0 / 64.0).re - flat_top[i] as f32).abs() < 1e-6);
+        }
+        assert!((Window::Nuttall.value(32, 1.0 / 64.0) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_fir_windowed_sinc() {
+        use filter::FrequencyResponse;
+        use fir_design::{Fir, FirBand};
+        use windows::Window;
+        let sr = 48000.0;
+        let fir = |band: FirBand| Fir::<f64>::new(&fir_design::windowed_sinc(band, 255, Window::Blackman, sr));
+        let lp = fir(FirBand::Lowpass(4000.0));
+        assert!(lp.magnitude_db(1000.0, sr).abs() < 0.01);
+        assert!((lp.magnitude_db(4000.0, sr) + 6.02).abs() < 0.1);
+        assert!((8000..24000).step_by(100).all(|f| lp.magnitude_db(f as f64, sr) < -70.0));
+
+        let hp = fir(FirBand::Highpass(4000.0));
+        assert!(hp.magnitude_db(10000.0, sr).abs() < 0.01 && hp.magnitude_db(1000.0, sr) < -70.0);
+        let bp = fir(FirBand::Bandpass(4000.0, 10000.0));
+        assert!(bp.magnitude_db(7000.0, sr).abs() < 0.01);
+        assert!(bp.magnitude_db(1000.0, sr) < -70.0 && bp.magnitude_db(15000.0, sr) < -70.0);
+        let bs = fir(FirBand::Bandstop(4000.0, 10000.0));
+        assert!(bs.magnitude_db(7000.0, sr) < -70.0);
+        assert!(bs.magnitude_db(1000.0, sr).abs() < 0.01 && bs.magnitude_db(15000.0, sr).abs() < 0.01);
+
+        // the runtime filter matches the designed response
+        let mut lp = fir(FirBand::Lowpass(4000.0));
+        assert!(lp.latency() == 127.0);
+        let taps = fir_design::windowed_sinc(FirBand::Lowpass(4000.0), 255, Window::Blackman, sr);
+        for (n, tap) in taps.iter().enumerate() {
+            assert!((lp.filter(if n == 0 { 1.0 } else { 0.0 }) - tap).abs() < 1e-15);
+        }
+
+        // the Hilbert transformer turns a sine into minus a cosine
+        let mut hilbert = Fir::<f64>::new(&fir_design::hilbert(255, Window::Blackman));
+        let w = std::f64::consts::TAU * 1000.0 / sr;
+        for n in 0..2000 {
+            let y = hilbert.filter((w * n as f64).sin());
+            if n > 255 {
+                assert!((y + (w * (n as f64 - 127.0)).cos()).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fir_remez() {
+        use filter::FrequencyResponse;
+        use fir_design::{Fir, RemezBand};
+        let sr = 48000.0;
+        let bands = [
+            RemezBand { start: 0.0, end: 4000.0, gain: 1.0, weight: 1.0 },
+            RemezBand { start: 6000.0, end: 24000.0, gain: 0.0, weight: 10.0 },
+        ];
+        let fir = Fir::<f64>::new(&fir_design::remez(61, &bands, sr).unwrap());
+        let passband: Vec<f64> = (0..=400).map(|i| fir.response(i as f64 * 10.0, sr).norm()).collect();
+        let stopband: Vec<f64> = (600..=2400).map(|i| fir.response(i as f64 * 10.0, sr).norm()).collect();
+        let ripple_pass = passband.iter().fold(0.0f64, |acc, g| acc.max((g - 1.0).abs()));
+        let ripple_stop = stopband.iter().fold(0.0f64, |acc, g| acc.max(*g));
+        // equiripple, with the error weighted 10 times more in the stopband
+        assert!((ripple_pass / ripple_stop - 10.0).abs() < 0.01);
+        assert!(20.0 * ripple_stop.log10() < -55.0);
+
+        // more than two bands, and long filters
+        let bands = [
+            RemezBand { start: 0.0, end: 3000.0, gain: 0.0, weight: 1.0 },
+            RemezBand { start: 4000.0, end: 8000.0, gain: 1.0, weight: 1.0 },
+            RemezBand { start: 9000.0, end: 24000.0, gain: 0.0, weight: 1.0 },
+        ];
+        for len in [51, 131, 201] {
+            let fir = Fir::<f64>::new(&fir_design::remez(len, &bands, sr).unwrap());
+            let ripple: Vec<f64> = bands.iter().map(|b| {
+                (0..=1000).map(|i| b.start + (b.end - b.start) * i as f64 / 1000.0)
+                    .fold(0.0f64, |acc, f| acc.max((fir.response(f, sr).norm() - b.gain).abs()))
+            }).collect();
+            assert!(ripple.iter().all(|r| (r / ripple[0] - 1.0).abs() < 0.02), "{}: {:?}", len, ripple);
+            if len == 201 {
+                assert!(ripple[0] < 1e-3);
+            }
+        }
+        let bands = [
+            RemezBand { start: 0.0, end: 0.2, gain: 1.0, weight: 1.0 },
+            RemezBand { start: 0.25, end: 0.5, gain: 0.0, weight: 1.0 },
+        ];
+        let fir = Fir::<f64>::new(&fir_design::remez(221, &bands, 1.0).unwrap());
+        assert!((0..=200).all(|i| (fir.response(i as f64 * 0.001, 1.0).norm() - 1.0).abs() < 1e-8));
+        assert!((0..=250).all(|i| fir.response(0.25 + i as f64 * 0.001, 1.0).norm() < 1e-8));
+
+        assert!(fir_design::remez(61, &[], sr).is_none());
+        // touching bands have no transition band
+        let bands = [
+            RemezBand { start: 0.0, end: 0.2, gain: 1.0, weight: 1.0 },
+            RemezBand { start: 0.2, end: 0.5, gain: 0.0, weight: 1.0 },
+        ];
+        assert!(fir_design::remez(61, &bands, 1.0).is_none());
+    }
+
+    #[test]
+    fn test_oversampler_latency() {
+        let sr = 48000.0;
+        let f = 500.0;
+        for filter in [OversamplingFilter::Fir, OversamplingFilter::Iir] {
+            for factor in [OversamplingFactor::Two, OversamplingFactor::Four, OversamplingFactor::Eight, OversamplingFactor::Sixteen] {
+                let mut os = Oversampler::new(factor, filter);
+                assert_eq!(os.ratio(), factor.ratio());
+                let mut err = 0.0f64;
+                for n in 0..4800 {
+                    let x = (std::f64::consts::TAU * f * n as f64 / sr).sin();
+                    let y = os.process(x as f32, |x| x) as f64;
+                    if n > 1000 {
+                        let expected = (std::f64::consts::TAU * f * (n as f64 - os.latency()) / sr).sin();
+                        err = err.max((y - expected).abs());
+                    }
+                }
+                assert!(err < 1e-3, "{:?} {:?}: error {}", filter, factor, err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_oversampler_aliasing() {
+        // x^3 of a 15kHz sine has a harmonic at 45kHz, which aliases to 3kHz
+        // at a 48kHz sample rate
+        use rustfft::num_complex::Complex;
+        let sr = 48000.0;
+        let amplitude = |y: &[f32], freq: f64| -> f64 {
+            let sum: Complex<f64> = y.iter().enumerate()
+                .map(|(n, y)| Complex::new(0.0, -std::f64::consts::TAU * freq * n as f64 / sr).exp() * *y as f64)
+                .sum();
+            2.0 * sum.norm() / y.len() as f64
+        };
+        let input: Vec<f32> = (0..2 * 48000)
+            .map(|n| (std::f64::consts::TAU * 15000.0 * n as f64 / sr).sin() as f32)
+            .collect();
+
+        let naive: Vec<f32> = input[48000..].iter().map(|x| x * x * x).collect();
+        assert!((amplitude(&naive, 3000.0) - 0.25).abs() < 1e-3);
+
+        for filter in [OversamplingFilter::Fir, OversamplingFilter::Iir] {
+            for factor in [OversamplingFactor::Two, OversamplingFactor::Sixteen] {
+                let mut os = Oversampler::new(factor, filter);
+                let output: Vec<f32> = input.iter().map(|x| os.process(*x, |x| x * x * x)).collect();
+                let output = &output[48000..];
+                assert!((amplitude(output, 15000.0) - 0.75).abs() < 0.01);
+                assert!(amplitude(output, 3000.0) < 1e-3, "{:?} {:?}", filter, factor);
+            }
+        }
+    }
+
+    #[test]
+    fn test_adaa() {
+        use rustfft::num_complex::Complex;
+        // power of the odd harmonics of a 7kHz sine below 24kHz which are
+        // not harmonics, i.e. aliases
+        let sr = 48000.0;
+        let alias_db = |y: &[f32]| -> f64 {
+            let power: f64 = (1..24).filter(|k| k % 7 != 0).map(|k| {
+                let sum: Complex<f64> = y.iter().enumerate()
+                    .map(|(n, y)| Complex::new(0.0, -std::f64::consts::TAU * k as f64 * 1000.0 * n as f64 / sr).exp() * *y as f64)
+                    .sum();
+                (2.0 * sum.norm() / y.len() as f64).powi(2)
+            }).sum();
+            10.0 * power.log10()
+        };
+        let input: Vec<f32> = (0..4800 + 100)
+            .map(|n| 4.0 * (std::f64::consts::TAU * 7000.0 * n as f64 / sr).sin() as f32)
+            .collect();
+
+        for shaper in [Waveshaper::HardClip, Waveshaper::Tanh, Waveshaper::MuLaw(0.9), Waveshaper::VarClip(0.5)] {
+            let naive: Vec<f32> = input.iter().map(|x| shaper.apply(*x)).collect();
+            let mut adaa1 = Adaa1::new(shaper);
+            let first: Vec<f32> = input.iter().map(|x| adaa1.process(*x)).collect();
+            let mut adaa2 = Adaa2::new(shaper);
+            let second: Vec<f32> = input.iter().map(|x| adaa2.process(*x)).collect();
+            let naive = alias_db(&naive[100..]);
+            let first = alias_db(&first[100..]);
+            let second = alias_db(&second[100..]);
+            assert!(first < naive - 10.0, "{:?}: {} {}", shaper, naive, first);
+            assert!(second < first - 10.0, "{:?}: {} {}", shaper, first, second);
+
+            // constant and slowly changing inputs, where the antiderivatives
+            // are ill-conditioned
+            let mut adaa1 = Adaa1::new(shaper);
+            let mut adaa2 = Adaa2::new(shaper);
+            for n in 0..1000 {
+                let x = 0.7 + n as f32 * 1e-7;
+                let (y1, y2) = (adaa1.process(x), adaa2.process(x));
+                if n > 2 {
+                    assert!((y1 - shaper.apply(x)).abs() < 1e-5);
+                    assert!((y2 - shaper.apply(x)).abs() < 1e-5);
+                }
+            }
+
+            // a NaN input does not panic, and a reset recovers from it
+            adaa1.process(f32::NAN);
+            adaa2.process(f32::NAN);
+            adaa1.reset();
+            adaa2.reset();
+            assert!(adaa1.process(0.5).is_finite() && adaa2.process(0.5).is_finite());
+        }
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let sr = 48000.0;
+        // magnetisation when the input crosses 0 upwards and downwards, and
+        // peak magnetisation
+        let run = |h: &mut Hysteresis, amplitude: f64, freq: f64| -> (f32, f32, f32) {
+            let (mut rising, mut falling, mut peak) = (0.0, 0.0, 0.0f32);
+            let mut x_z1 = 0.0;
+            for n in 0..48000 {
+                let x = (amplitude * (std::f64::consts::TAU * freq * n as f64 / sr).sin()) as f32;
+                let y = h.process(x);
+                assert!(y.is_finite());
+                if n > 24000 {
+                    peak = peak.max(y.abs());
+                    if x_z1 < 0.0 && x >= 0.0 {
+                        rising = y;
+                    }
+                    if x_z1 > 0.0 && x <= 0.0 {
+                        falling = y;
+                    }
+                }
+                x_z1 = x;
+            }
+            (rising, falling, peak)
+        };
+
+        // the magnetisation lags the field, and both solvers agree at low
+        // frequencies
+        let mut rk2 = Hysteresis::new(HysteresisSolver::Rk2, sr as f32);
+        let mut rk4 = Hysteresis::new(HysteresisSolver::Rk4, sr as f32);
+        let (rising, falling, peak) = run(&mut rk4, 1.0, 100.0);
+        assert!(rising < -0.03 && falling > 0.03);
+        assert!(peak < 1.0);
+        let (rising_rk2, falling_rk2, peak_rk2) = run(&mut rk2, 1.0, 100.0);
+        assert!((rising - rising_rk2).abs() < 1e-3);
+        assert!((falling - falling_rk2).abs() < 1e-3);
+        assert!((peak - peak_rk2).abs() < 1e-3);
+
+        // a narrower loop has less remanence
+        rk4.reset();
+        rk4.set_width(0.0);
+        let (rising, falling, _) = run(&mut rk4, 1.0, 100.0);
+        assert!(rising.abs() < 0.01 && falling.abs() < 0.01);
+
+        // extreme settings stay bounded
+        rk4.reset();
+        rk4.set_width(1.0);
+        rk4.set_drive(1.0);
+        rk4.set_saturation(1.0);
+        let (_, _, peak) = run(&mut rk4, 10.0, 5000.0);
+        assert!(peak <= 1.0);
     }
-}
\ No newline at end of file
+}
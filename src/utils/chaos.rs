@@ -1,4 +1,4 @@
-/**
+/*!
 *  Utilities for generating random numbers and noise.
 *
 *  Passing the same instance of Xoshiro256Plus will correlate all the variables
@@ -158,7 +158,7 @@ impl Rng {
 
     /// geiger noise (random triggers)
     /// 
-    pub fn geiger_noise(&mut self, rate: f32, random: f32) -> f32 {
+    pub fn geiger_noise(&mut self, _rate: f32, _random: f32) -> f32 {
         0.0
     }
 }
@@ -37,4 +37,26 @@ pub fn pre_post_gains(x: f32) -> (f32, f32) {
     } else {
         (1.0 + x, 1.0 / (1.0 + x))
     }
-}
\ No newline at end of file
+}
+
+/// Fast approximation of `tan(x)` for x in [0, pi/2), from a 7/6 Padé
+/// approximant. The relative error, measured in f32, is about 3e-7 up to
+/// 0.4*pi and 5e-7 up to 0.45*pi, and grows towards the pole. Use it to
+/// compute filter coefficients at audio rate.
+#[inline(always)]
+pub fn fast_tan(x: f32) -> f32 {
+    let x2 = x*x;
+    x*(135135.0 - x2*(17325.0 - x2*(378.0 - x2)))
+        / (135135.0 - x2*(62370.0 - x2*(3150.0 - 28.0*x2)))
+}
+
+/// Fast approximation of `tanh(x)`, from a 7/6 Padé approximant. The
+/// absolute error is below 1e-4 for |x| < 3.5, and the output is clamped to
+/// [-1, 1] beyond. Use it for saturation in the inner loop of filters.
+#[inline(always)]
+pub fn fast_tanh(x: f32) -> f32 {
+    let x2 = x*x;
+    let y = x*(135135.0 + x2*(17325.0 + x2*(378.0 + x2)))
+        / (135135.0 + x2*(62370.0 + x2*(3150.0 + 28.0*x2)));
+    return y.clamp(-1.0, 1.0);
+}